@@ -7,10 +7,7 @@ use std::path::Path;
 pub trait AocImplementation<T> {
 
     fn start(&self, day: i32) {
-        download_input_file(day);
-        let contents = fs::read_to_string(get_day_filename(day)).expect("Failed to read input file");
-
-        let parsed = self.process_input(&contents);
+        let parsed = self.load_input(day);
 
         let answer = self.execute(parsed);
 
@@ -20,10 +17,23 @@ pub trait AocImplementation<T> {
         }
     }
 
+    // Reads the input file and parses it, days can replace this to read it some other way
+    fn load_input(&self, day: i32) -> Vec<T> {
+        let contents = fs::read_to_string(input_file(day)).expect("Failed to read input file");
+
+        self.process_input(&contents)
+    }
+
     fn process_input(&self, input: &str) -> Vec<T>;
     fn execute(&self, input: Vec<T>) -> Option<i32>;
 }
 
+// The input file for the day, downloaded first if it isn't there yet
+pub fn input_file(day: i32) -> String {
+    download_input_file(day);
+    get_day_filename(day)
+}
+
 fn get_day_filename(day: i32) -> String {
     format!("day{}/input.txt", day)
 }
@@ -0,0 +1,74 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use day1::bulk::{self, Table, Totals};
+use day1::fuel::Formula;
+
+// How the solution used to follow the fuel for the fuel, one call per step
+fn calculate_additional_fuel(fuel: i32) -> i32 {
+    let additional_fuel = (fuel / 3) - 2;
+    if additional_fuel <= 0 {
+        0
+    } else {
+        additional_fuel + calculate_additional_fuel(additional_fuel)
+    }
+}
+
+// A million masses the size of the puzzle's
+fn masses() -> Vec<u64> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    (0..1_000_000)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            50_000 + state % 100_000
+        })
+        .collect()
+}
+
+fn per_module(c: &mut Criterion) {
+    let masses = masses();
+    let small: Vec<i32> = masses.iter().map(|&mass| mass as i32).collect();
+
+    c.bench_function("recursive", |b| {
+        b.iter(|| {
+            let total: i64 = small.iter().map(|&mass| {
+                let fuel = (mass / 3) - 2;
+                (fuel + calculate_additional_fuel(fuel)) as i64
+            }).sum();
+            black_box(total)
+        })
+    });
+
+    c.bench_function("iterative", |b| {
+        b.iter(|| {
+            let total: i64 = masses.iter().map(|&mass| Formula::PUZZLE.fuel_with_fuel(mass as i64).unwrap()).sum();
+            black_box(total)
+        })
+    });
+
+    let table = Table::new(Formula::PUZZLE);
+    c.bench_function("table", |b| {
+        b.iter(|| {
+            let mut totals = Totals::default();
+            for &mass in &masses {
+                table.add(&mut totals, mass).unwrap();
+            }
+            black_box(totals)
+        })
+    });
+}
+
+fn streaming(c: &mut Criterion) {
+    let text: String = masses().iter().map(|mass| format!("{}\n", mass)).collect();
+
+    c.bench_function("stream", |b| b.iter(|| black_box(bulk::sum(Formula::PUZZLE, text.as_bytes()).unwrap())));
+
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    c.bench_function("stream in parallel", |b| {
+        b.iter(|| black_box(bulk::sum_parallel(Formula::PUZZLE, text.as_bytes(), threads).unwrap()))
+    });
+}
+
+criterion_group!(benches, per_module, streaming);
+criterion_main!(benches);
@@ -0,0 +1,349 @@
+// Fuel for more modules than fit in memory, read a line at a time. Masses are 64 bit and the
+// sums 128 bit, so only formulas that divide by one can overflow them.
+use std::fmt;
+use std::io::{self, BufRead};
+use std::num::ParseIntError;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+use crate::fuel::Formula;
+
+// The fuel for fuel lighter than this is looked up instead of followed down the chain. For the
+// puzzle's masses that leaves a single step per module.
+const TABLE_SIZE: u64 = 1 << 16;
+// How many bytes of lines a thread gets at a time
+const CHUNK_BYTES: usize = 1 << 20;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    // Lines are counted from 1
+    Parse { line: usize, error: ParseIntError },
+    Overflow,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "{}", error),
+            Error::Parse { line, error } => write!(f, "line {}: {}", line, error),
+            Error::Overflow => write!(f, "the fuel doesn't fit in 128 bits"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Totals {
+    pub modules: u64,
+    pub fuel: u128,
+    pub fuel_for_fuel: u128,
+}
+
+impl Totals {
+    pub fn total(&self) -> Result<u128, Error> {
+        self.fuel.checked_add(self.fuel_for_fuel).ok_or(Error::Overflow)
+    }
+
+    fn add(&mut self, other: Totals) -> Result<(), Error> {
+        self.modules += other.modules;
+        self.fuel = self.fuel.checked_add(other.fuel).ok_or(Error::Overflow)?;
+        self.fuel_for_fuel = self.fuel_for_fuel.checked_add(other.fuel_for_fuel).ok_or(Error::Overflow)?;
+        self.total().map(|_| ())
+    }
+}
+
+pub struct Table {
+    formula: Formula,
+    // Indexed by an amount of fuel
+    fuel_for_fuel: Vec<u128>,
+}
+
+impl Table {
+    pub fn new(formula: Formula) -> Table {
+        // Fuel always weighs less than what it's for, so the chain of anything in the table is
+        // in it already
+        let mut fuel_for_fuel = vec![0u128; TABLE_SIZE as usize];
+        for fuel in 1..TABLE_SIZE {
+            let more = formula.fuel_u64(fuel);
+            if more > 0 {
+                fuel_for_fuel[fuel as usize] = more as u128 + fuel_for_fuel[more as usize];
+            }
+        }
+        Table { formula, fuel_for_fuel }
+    }
+
+    pub fn fuel_for_fuel(&self, fuel: u64) -> Result<u128, Error> {
+        // Only subtracting takes a step per unit of the subtrahend, far too many to follow
+        if self.formula.divisor() == 1 {
+            return subtracted(fuel, self.formula.subtrahend() as u64);
+        }
+
+        let mut total = 0u128;
+        let mut last = fuel;
+        while last >= TABLE_SIZE {
+            last = self.formula.fuel_u64(last);
+            total += last as u128;
+        }
+        Ok(total + self.fuel_for_fuel[last as usize])
+    }
+
+    pub fn add(&self, totals: &mut Totals, mass: u64) -> Result<(), Error> {
+        let fuel = self.formula.fuel_u64(mass);
+        let module = Totals { modules: 1, fuel: fuel as u128, fuel_for_fuel: self.fuel_for_fuel(fuel)? };
+        totals.add(module)
+    }
+}
+
+// The sum of `fuel - step`, `fuel - 2 * step` and so on for as long as they're positive, as
+// the number of terms times their average
+fn subtracted(fuel: u64, step: u64) -> Result<u128, Error> {
+    if fuel <= step {
+        return Ok(0);
+    }
+    let count = ((fuel - 1) / step) as u128;
+    let ends = (fuel - step) as u128 + (fuel as u128 - count * step as u128);
+    // With an odd count the ends add up to twice the middle term, so one of them halves evenly
+    let total = if count.is_multiple_of(2) { (count / 2).checked_mul(ends) } else { count.checked_mul(ends / 2) };
+    total.ok_or(Error::Overflow)
+}
+
+// One mass per line, where blank lines are skipped
+pub fn sum<R: BufRead>(formula: Formula, mut reader: R) -> Result<Totals, Error> {
+    let table = Table::new(formula);
+    let mut totals = Totals::default();
+    read_chunks(&mut reader, |first, chunk| sum_chunk(&table, &mut totals, first, &chunk))?;
+    Ok(totals)
+}
+
+// The same as `sum`, with the lines parsed and summed on `threads` threads while this one reads
+// them. A parse error on the earliest line wins, like reading them in order would.
+pub fn sum_parallel<R: BufRead>(formula: Formula, mut reader: R, threads: usize) -> Result<Totals, Error> {
+    let table = Table::new(formula);
+    let (sender, receiver) = mpsc::sync_channel::<(usize, Vec<u8>)>(threads * 2);
+    let receiver = Mutex::new(receiver);
+
+    let (read, results) = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.max(1))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut totals = Totals::default();
+                    let mut failed = None;
+                    // Keeps taking chunks after failing, so the reader never waits on it
+                    take_each(&receiver, |(first, chunk)| {
+                        if failed.is_none() {
+                            failed = sum_chunk(&table, &mut totals, first, &chunk).err();
+                        }
+                    });
+                    failed.map_or(Ok(totals), Err)
+                })
+            })
+            .collect();
+
+        let read = read_chunks(&mut reader, |first, chunk| {
+            sender.send((first, chunk)).unwrap();
+            Ok(())
+        });
+        drop(sender);
+        let results: Vec<_> = workers.into_iter().map(|worker| worker.join().unwrap()).collect();
+        (read, results)
+    });
+    read?;
+
+    let mut totals = Totals::default();
+    let mut first_error: Option<Error> = None;
+    for result in results {
+        match result {
+            Ok(part) => {
+                if let Err(error) = totals.add(part) {
+                    first_error.get_or_insert(error);
+                }
+            }
+            Err(error) => {
+                if first_error.as_ref().is_none_or(|first| line_of(&error) < line_of(first)) {
+                    first_error = Some(error);
+                }
+            }
+        }
+    }
+    first_error.map_or(Ok(totals), Err)
+}
+
+// Works through what's sent, sharing it with the other workers taking from the same receiver.
+// The lock is only held while taking a value, so they can all work at once.
+fn take_each<T, F: FnMut(T)>(receiver: &Mutex<mpsc::Receiver<T>>, mut work: F) {
+    loop {
+        let next = receiver.lock().unwrap().recv();
+        let Ok(value) = next else { return };
+        work(value);
+    }
+}
+
+// Hands on whole lines about a megabyte at a time, with the number of the first line in each
+fn read_chunks<R, F>(reader: &mut R, mut send: F) -> Result<(), Error>
+where
+    R: BufRead,
+    F: FnMut(usize, Vec<u8>) -> Result<(), Error>,
+{
+    let mut first = 1;
+    loop {
+        let mut chunk = Vec::with_capacity(CHUNK_BYTES + 32);
+        while chunk.len() < CHUNK_BYTES {
+            let buffer = reader.fill_buf()?;
+            if buffer.is_empty() {
+                break;
+            }
+            let take = buffer.len().min(CHUNK_BYTES - chunk.len());
+            chunk.extend_from_slice(&buffer[..take]);
+            reader.consume(take);
+        }
+        if !chunk.ends_with(b"\n") {
+            reader.read_until(b'\n', &mut chunk)?;
+        }
+        if chunk.is_empty() {
+            return Ok(());
+        }
+
+        let lines = chunk.iter().filter(|&&byte| byte == b'\n').count();
+        send(first, chunk)?;
+        first += lines;
+    }
+}
+
+fn sum_chunk(table: &Table, totals: &mut Totals, first: usize, chunk: &[u8]) -> Result<(), Error> {
+    for (index, line) in chunk.split(|&byte| byte == b'\n').enumerate() {
+        let text = String::from_utf8_lossy(line);
+        let text = text.trim();
+        if !text.is_empty() {
+            let mass = text.parse().map_err(|error| Error::Parse { line: first + index, error })?;
+            table.add(totals, mass)?;
+        }
+    }
+    Ok(())
+}
+
+fn line_of(error: &Error) -> usize {
+    match error {
+        Error::Parse { line, .. } => *line,
+        _ => usize::MAX,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fuel::Rounding;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    fn text(masses: &[u64]) -> String {
+        masses.iter().map(|mass| format!("{}\n", mass)).collect()
+    }
+
+    #[test]
+    fn agrees_with_the_formula() {
+        let formulas = [
+            Formula::PUZZLE,
+            Formula::new(2, 0, Rounding::Down).unwrap(),
+            Formula::new(1, 1, Rounding::Down).unwrap(),
+            Formula::new(1, 3, Rounding::Up).unwrap(),
+        ];
+        let masses = [0, 1, 14, 1969, 100_756, 70_000, 65_535, 65_536, 200_000, 3_000_000];
+        for formula in &formulas {
+            let table = Table::new(*formula);
+            for &mass in &masses {
+                let breakdown = formula.breakdown(mass as i64).unwrap();
+                let mut totals = Totals::default();
+                table.add(&mut totals, mass).unwrap();
+                assert_eq!((totals.fuel, totals.fuel_for_fuel), (breakdown.fuel as u128, breakdown.fuel_for_fuel as u128));
+            }
+        }
+    }
+
+    #[test]
+    fn adds_up_long_chains_at_once() {
+        // A step for every unit of fuel would never finish
+        let table = Table::new(Formula::new(1, 1, Rounding::Down).unwrap());
+        let mass = u64::MAX / 2;
+        let mut totals = Totals::default();
+        table.add(&mut totals, mass).unwrap();
+        let fuel = (mass - 1) as u128;
+        assert_eq!(totals, Totals { modules: 1, fuel, fuel_for_fuel: (fuel - 1) * fuel / 2 });
+
+        // Each one needs close to 2^127, so three don't fit
+        let input = text(&[u64::MAX; 3]);
+        assert!(matches!(sum(Formula::new(1, 1, Rounding::Down).unwrap(), input.as_bytes()), Err(Error::Overflow)));
+    }
+
+    #[test]
+    fn sums_streams() {
+        let input = text(&[12, 14, 1969, 100_756]) + "\n";
+        let expected = Totals { modules: 4, fuel: 2 + 2 + 654 + 33583, fuel_for_fuel: 312 + 16763 };
+        assert_eq!(sum(Formula::PUZZLE, input.as_bytes()).unwrap(), expected);
+        assert_eq!(sum_parallel(Formula::PUZZLE, input.as_bytes(), 3).unwrap(), expected);
+    }
+
+    #[test]
+    fn sums_in_parallel() {
+        // Enough for every thread to get a chunk
+        let masses: Vec<u64> = (0..400_000u64).map(|i| i * 7919 % 1_000_003 + u64::MAX / 2 * (i % 2)).collect();
+        let input = text(&masses);
+        let expected = sum(Formula::PUZZLE, input.as_bytes()).unwrap();
+        assert_eq!(expected.modules, 400_000);
+        assert!(expected.total().unwrap() > u64::MAX as u128);
+        assert_eq!(sum_parallel(Formula::PUZZLE, input.as_bytes(), 4).unwrap(), expected);
+    }
+
+    #[test]
+    fn works_on_chunks_at_the_same_time() {
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Mutex::new(receiver);
+        sender.send(0).unwrap();
+        sender.send(1).unwrap();
+        drop(sender);
+
+        // Each worker holds on to its chunk until the other one has one too
+        let started = AtomicUsize::new(0);
+        let together = thread::scope(|scope| {
+            let workers: Vec<_> = (0..2)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut together = false;
+                        take_each(&receiver, |_| {
+                            started.fetch_add(1, Ordering::SeqCst);
+                            let deadline = Instant::now() + Duration::from_secs(5);
+                            while started.load(Ordering::SeqCst) < 2 && Instant::now() < deadline {
+                                thread::yield_now();
+                            }
+                            together = started.load(Ordering::SeqCst) == 2;
+                        });
+                        together
+                    })
+                })
+                .collect();
+            workers.into_iter().map(|worker| worker.join().unwrap()).collect::<Vec<_>>()
+        });
+        assert_eq!(together, vec![true, true]);
+    }
+
+    #[test]
+    fn reports_the_first_bad_line() {
+        // A line for every four bytes, with the bad one in the third chunk
+        let mut input = text(&vec![100; CHUNK_BYTES]);
+        input.insert_str(CHUNK_BYTES * 2 + 4, "x\n");
+        input.push_str("-1\n");
+        for result in [sum(Formula::PUZZLE, input.as_bytes()), sum_parallel(Formula::PUZZLE, input.as_bytes(), 4)] {
+            match result {
+                Err(Error::Parse { line, .. }) => assert_eq!(line, CHUNK_BYTES / 2 + 2),
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+}
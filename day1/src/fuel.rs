@@ -0,0 +1,285 @@
+// The rocket equation: a module needs its mass divided, rounded and reduced by a fixed amount
+// in fuel, and that fuel needs fuel of its own. Masses too small to need fuel need none.
+use std::fmt;
+use std::ops::RangeInclusive;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Rounding {
+    Down,
+    Up,
+    // Halves round up
+    Nearest,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+    // The divisor has to be positive and the subtrahend can't be negative
+    InvalidFormula,
+    // Fuel for the fuel would never get down to nothing, because some mass needs as much fuel
+    // as it weighs
+    Unbounded,
+    Overflow { mass: i64 },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidFormula => write!(f, "the divisor must be positive and the subtrahend not negative"),
+            Error::Unbounded => write!(f, "fuel for the fuel never runs out"),
+            Error::Overflow { mass } => write!(f, "fuel for mass {} doesn't fit in 64 bits", mass),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Formula {
+    divisor: i64,
+    subtrahend: i64,
+    rounding: Rounding,
+}
+
+// Fuel for one module
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Breakdown {
+    pub mass: i64,
+    pub fuel: i64,
+    // Fuel for the fuel, and for that fuel, and so on
+    pub fuel_for_fuel: i64,
+}
+
+impl Breakdown {
+    pub fn total(&self) -> i64 {
+        self.fuel + self.fuel_for_fuel
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Report {
+    pub modules: Vec<Breakdown>,
+    pub fuel: i64,
+    pub fuel_for_fuel: i64,
+}
+
+impl Report {
+    pub fn total(&self) -> i64 {
+        self.fuel + self.fuel_for_fuel
+    }
+}
+
+impl Formula {
+    // Divide by three, round down and subtract two
+    pub const PUZZLE: Formula = Formula { divisor: 3, subtrahend: 2, rounding: Rounding::Down };
+
+    pub fn new(divisor: i64, subtrahend: i64, rounding: Rounding) -> Result<Formula, Error> {
+        if divisor <= 0 || subtrahend < 0 {
+            return Err(Error::InvalidFormula);
+        }
+
+        // Every mass needs less fuel than it weighs once a mass of 1 does, unless nothing is
+        // divided away
+        let formula = Formula { divisor, subtrahend, rounding };
+        if formula.fuel(1) >= 1 || (divisor == 1 && subtrahend == 0) {
+            return Err(Error::Unbounded);
+        }
+        Ok(formula)
+    }
+
+    pub fn divisor(&self) -> i64 {
+        self.divisor
+    }
+
+    pub fn subtrahend(&self) -> i64 {
+        self.subtrahend
+    }
+
+    pub fn fuel(&self, mass: i64) -> i64 {
+        if mass <= 0 {
+            return 0;
+        }
+        // Never more than the mass, so it fits
+        self.fuel_u64(mass as u64) as i64
+    }
+
+    pub fn fuel_u64(&self, mass: u64) -> u64 {
+        let divisor = self.divisor as u64;
+        let quotient = mass / divisor;
+        let remainder = mass % divisor;
+        let rounded = match self.rounding {
+            Rounding::Down => quotient,
+            Rounding::Up if remainder > 0 => quotient + 1,
+            Rounding::Up => quotient,
+            // Written so it can't overflow for big divisors
+            Rounding::Nearest if remainder >= divisor - remainder => quotient + 1,
+            Rounding::Nearest => quotient,
+        };
+        rounded.saturating_sub(self.subtrahend as u64)
+    }
+
+    // Fuel for the mass, and then for that fuel until it needs no more
+    pub fn breakdown(&self, mass: i64) -> Result<Breakdown, Error> {
+        let fuel = self.fuel(mass);
+
+        let mut fuel_for_fuel = 0i64;
+        let mut last = fuel;
+        loop {
+            let more = self.fuel(last);
+            if more == 0 {
+                break;
+            }
+            fuel_for_fuel = fuel_for_fuel.checked_add(more).ok_or(Error::Overflow { mass })?;
+            last = more;
+        }
+
+        Ok(Breakdown { mass, fuel, fuel_for_fuel })
+    }
+
+    pub fn fuel_with_fuel(&self, mass: i64) -> Result<i64, Error> {
+        let breakdown = self.breakdown(mass)?;
+        breakdown.fuel.checked_add(breakdown.fuel_for_fuel).ok_or(Error::Overflow { mass })
+    }
+
+    // The masses of at least 1 that need exactly `fuel`, which may be none when the formula
+    // skips it
+    pub fn masses_for_fuel(&self, fuel: i64) -> Option<RangeInclusive<i64>> {
+        masses_where(fuel, |mass| Ok(self.fuel(mass)))
+    }
+
+    pub fn masses_for_fuel_with_fuel(&self, total: i64) -> Option<RangeInclusive<i64>> {
+        masses_where(total, |mass| self.fuel_with_fuel(mass))
+    }
+
+    pub fn report(&self, masses: &[i64]) -> Result<Report, Error> {
+        let mut report = Report { modules: Vec::with_capacity(masses.len()), fuel: 0, fuel_for_fuel: 0 };
+        for &mass in masses {
+            let breakdown = self.breakdown(mass)?;
+            let overflow = Error::Overflow { mass };
+            report.fuel = report.fuel.checked_add(breakdown.fuel).ok_or(overflow)?;
+            report.fuel_for_fuel = report.fuel_for_fuel.checked_add(breakdown.fuel_for_fuel).ok_or(overflow)?;
+            report.fuel.checked_add(report.fuel_for_fuel).ok_or(overflow)?;
+            report.modules.push(breakdown);
+        }
+        Ok(report)
+    }
+}
+
+// Both kinds of fuel only ever grow with the mass, so the masses giving one amount follow each
+// other and can be searched for. Too much fuel to count is more than any amount.
+fn masses_where<F: Fn(i64) -> Result<i64, Error>>(fuel: i64, fuel_for: F) -> Option<RangeInclusive<i64>> {
+    let above = |mass: i64, fuel: i64| fuel_for(mass).map_or(true, |needed| needed > fuel);
+    let first_above = |fuel: i64| {
+        if !above(i64::MAX, fuel) {
+            return None;
+        }
+        let (mut low, mut high) = (1i64, i64::MAX);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if above(middle, fuel) {
+                high = middle;
+            } else {
+                low = middle + 1;
+            }
+        }
+        Some(low)
+    };
+
+    if fuel < 0 {
+        return None;
+    }
+    let first = if fuel == 0 { 1 } else { first_above(fuel - 1)? };
+    let last = first_above(fuel).map_or(i64::MAX, |mass| mass - 1);
+    if first > last {
+        return None;
+    }
+    Some(first..=last)
+}
+
+impl Default for Formula {
+    fn default() -> Formula {
+        Formula::PUZZLE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_puzzle() {
+        let formula = Formula::PUZZLE;
+        assert_eq!(formula.fuel(1969), 654);
+        assert_eq!(formula.fuel(2), 0);
+        assert_eq!(formula.breakdown(1969), Ok(Breakdown { mass: 1969, fuel: 654, fuel_for_fuel: 312 }));
+        assert_eq!(formula.fuel_with_fuel(100_756), Ok(50346));
+
+        let report = formula.report(&[14, 1969, 100_756]).unwrap();
+        assert_eq!(report.modules.len(), 3);
+        assert_eq!((report.fuel, report.total()), (2 + 654 + 33583, 2 + 966 + 50346));
+        assert_eq!(formula.report(&[]).unwrap().total(), 0);
+    }
+
+    #[test]
+    fn matches_the_examples() {
+        let formula = Formula::PUZZLE;
+        for &(mass, fuel) in &[(12, 2), (14, 2), (1969, 654), (100_756, 33583)] {
+            assert_eq!(formula.fuel(mass), fuel, "mass {}", mass);
+        }
+        for &(mass, total) in &[(14, 2), (1969, 966), (100_756, 50346)] {
+            assert_eq!(formula.fuel_with_fuel(mass), Ok(total), "mass {}", mass);
+        }
+    }
+
+    #[test]
+    fn rounds() {
+        let up = Formula::new(4, 1, Rounding::Up).unwrap();
+        let nearest = Formula::new(4, 1, Rounding::Nearest).unwrap();
+        assert_eq!((up.fuel(9), nearest.fuel(9), nearest.fuel(10)), (2, 1, 2));
+        assert_eq!(Formula::new(i64::MAX, 0, Rounding::Nearest).unwrap().fuel(i64::MAX - 1), 1);
+        assert_eq!(Formula::PUZZLE.fuel_u64(u64::MAX), u64::MAX / 3 - 2);
+    }
+
+    #[test]
+    fn rejects_formulas_that_never_end() {
+        assert_eq!(Formula::new(0, 2, Rounding::Down), Err(Error::InvalidFormula));
+        assert_eq!(Formula::new(3, -1, Rounding::Down), Err(Error::InvalidFormula));
+        assert_eq!(Formula::new(1, 0, Rounding::Down), Err(Error::Unbounded));
+        assert_eq!(Formula::new(2, 0, Rounding::Up), Err(Error::Unbounded));
+        assert!(Formula::new(2, 0, Rounding::Down).is_ok());
+    }
+
+    #[test]
+    fn follows_long_chains_without_recursing() {
+        // Every step only takes one off, which would be a million calls deep when recursing
+        let formula = Formula::new(1, 1, Rounding::Down).unwrap();
+        assert_eq!(formula.fuel_with_fuel(1_000_001), Ok(1_000_000 * 1_000_001 / 2));
+    }
+
+    #[test]
+    fn finds_the_masses_for_an_amount_of_fuel() {
+        let formula = Formula::PUZZLE;
+        assert_eq!(formula.masses_for_fuel(0), Some(1..=8));
+        assert_eq!(formula.masses_for_fuel(2), Some(12..=14));
+        assert_eq!(formula.masses_for_fuel(654), Some(1968..=1970));
+        assert_eq!(formula.masses_for_fuel(i64::MAX / 3 - 2), Some(i64::MAX - 1..=i64::MAX));
+        assert_eq!(formula.masses_for_fuel(-1), None);
+
+        let masses = formula.masses_for_fuel_with_fuel(966).unwrap();
+        assert!(masses.contains(&1969));
+        for mass in *masses.start() - 1..=*masses.end() + 1 {
+            assert_eq!(formula.fuel_with_fuel(mass) == Ok(966), masses.contains(&mass));
+        }
+        // Fuel of 8 needs none itself and fuel of 9 needs 1 more
+        assert_eq!(formula.masses_for_fuel_with_fuel(9), None);
+
+        let up = Formula::new(2, 1, Rounding::Up).unwrap();
+        assert_eq!(up.masses_for_fuel(3), Some(7..=8));
+    }
+
+    #[test]
+    fn reports_overflow() {
+        let formula = Formula::new(1, 1, Rounding::Down).unwrap();
+        assert_eq!(formula.fuel_with_fuel(i64::MAX), Err(Error::Overflow { mass: i64::MAX }));
+        assert_eq!(Formula::PUZZLE.report(&[i64::MAX; 3]), Err(Error::Overflow { mass: i64::MAX }));
+    }
+}
@@ -0,0 +1,3 @@
+pub mod bulk;
+pub mod fuel;
+pub mod plan;
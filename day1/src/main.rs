@@ -1,93 +1,52 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::BufReader;
+use std::{env, process, thread};
+
 use aoc_lib::AocImplementation;
+use day1::bulk;
+use day1::fuel::Formula;
 
 fn main() {
-    let day1 = Day1 {};
-
-    day1.start(1)
-}
-
-struct Day1 {}
-
-impl AocImplementation<i32> for Day1 {
-    fn process_input(&self, input: &str) -> Vec<i32> {
-        input.split('\n').map(|line| line.parse().unwrap()).collect()
+    // A file of masses too big to read in at once can be given instead of the puzzle input
+    if let Some(path) = env::args().nth(1) {
+        return sum_file(&path);
     }
 
-    fn execute(&self, input: Vec<i32>) -> Option<i32> {
-        let answer = input.into_iter()
-            .map(calculate_fuel)
-            .map(|fuel| fuel + calculate_additional_fuel(fuel))
-            .sum();
-        Some(answer)
-    }
-}
-
-fn calculate_fuel(mass: i32) -> i32 {
-    (mass / 3) - 2
-}
-
-fn calculate_additional_fuel(fuel: i32) -> i32 {
-    let additional_fuel = calculate_fuel(fuel);
+    let day1 = Day1 {};
 
-//    println!("Additional fuel {} fuel {}", additional_fuel, fuel);
-    if additional_fuel <= 0 {
-        0
-    } else {
-        additional_fuel + calculate_additional_fuel(additional_fuel)
-    }
+    day1.start(1)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    mod calculate_fuel_for_mass {
-        use super::*;
-
-        #[test]
-        fn example1() {
-            let result = calculate_fuel(12);
-            assert_eq!(result, 2);
-        }
-
-        #[test]
-        fn example2() {
-            let result = calculate_fuel(14);
-            assert_eq!(result, 2);
+fn sum_file(path: &str) {
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let result = File::open(path)
+        .map_err(bulk::Error::from)
+        .and_then(|file| bulk::sum_parallel(Formula::PUZZLE, BufReader::new(file), threads));
+
+    match result.and_then(|totals| Ok((totals, totals.total()?))) {
+        Ok((totals, total)) => {
+            println!("Modules: {}", totals.modules);
+            println!("Fuel: {}", totals.fuel);
+            println!("Fuel for fuel: {}", totals.fuel_for_fuel);
+            println!("Puzzle answer: {}", total);
         }
-
-        #[test]
-        fn example3() {
-            let result = calculate_fuel(1969);
-            assert_eq!(result, 654);
-        }
-
-        #[test]
-        fn example4() {
-            let result = calculate_fuel(100_756);
-            assert_eq!(result, 33583);
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            process::exit(1);
         }
     }
+}
 
-    mod calculate_rocket_fuel_mass_science {
-        use super::*;
-
-        #[test]
-        fn example1() {
-            let result = calculate_additional_fuel(2) + 2;
-            assert_eq!(result, 2);
-        }
+struct Day1 {}
 
-        #[test]
-        fn example2() {
-            let result = calculate_additional_fuel(654) + 654;
-            assert_eq!(result, 966);
-        }
+impl AocImplementation<i64> for Day1 {
+    fn process_input(&self, input: &str) -> Vec<i64> {
+        input.split('\n').map(|line| line.parse().unwrap()).collect()
+    }
 
-        #[test]
-        fn example3() {
-            let result = calculate_additional_fuel(33583) + 33583;
-            assert_eq!(result, 50346);
-        }
+    fn execute(&self, input: Vec<i64>) -> Option<i32> {
+        let report = Formula::PUZZLE.report(&input).ok()?;
+        i32::try_from(report.total()).ok()
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,135 @@
+// Picks which modules to launch when there isn't fuel for all of them, carrying as much mass as
+// possible. It's a knapsack with the fuel a module needs, its own fuel included, as the weight.
+use std::fmt;
+
+use crate::fuel::{self, Formula};
+
+// Planning takes 8 bytes for every unit of the budget, and a bit for every unit and module to
+// remember which were taken. At this budget that's 32 MiB, plus 512 KiB a module.
+const MAX_BUDGET: i64 = 1 << 22;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+    Fuel(fuel::Error),
+    // Any budget is fine when every module fits in it, or when this one is smaller
+    BudgetTooLarge { budget: i64 },
+    // The chosen modules weigh more than fits in 64 bits
+    Overflow,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Fuel(error) => write!(f, "{}", error),
+            Error::BudgetTooLarge { budget } => write!(f, "a budget of {} is too large to plan for, the most is {}", budget, MAX_BUDGET),
+            Error::Overflow => write!(f, "the modules weigh too much to add up"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<fuel::Error> for Error {
+    fn from(error: fuel::Error) -> Error {
+        Error::Fuel(error)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Plan {
+    // Indices into the masses, in order
+    pub modules: Vec<usize>,
+    pub mass: i64,
+    pub fuel: i64,
+}
+
+pub fn plan(formula: &Formula, masses: &[i64], budget: i64) -> Result<Plan, Error> {
+    let costs = masses.iter().map(|&mass| formula.fuel_with_fuel(mass)).collect::<Result<Vec<i64>, _>>()?;
+    let all = costs.iter().try_fold(0i64, |total, &cost| total.checked_add(cost));
+    if all.is_some_and(|all| all <= budget) {
+        return choose(masses, &costs, (0..masses.len()).collect());
+    }
+    if budget < 0 {
+        return Ok(Plan { modules: Vec::new(), mass: 0, fuel: 0 });
+    }
+    if budget > MAX_BUDGET {
+        return Err(Error::BudgetTooLarge { budget });
+    }
+
+    // The most mass for every budget, using the modules so far, and which budgets took each one
+    let width = budget as usize + 1;
+    let mut best = vec![0i64; width];
+    let mut taken = vec![vec![0u64; width.div_ceil(64)]; masses.len()];
+    for (index, (&mass, &cost)) in masses.iter().zip(&costs).enumerate() {
+        // Modules without mass aren't worth any fuel
+        if mass <= 0 || cost > budget {
+            continue;
+        }
+        let cost = cost as usize;
+        for fuel in (cost..width).rev() {
+            let with = best[fuel - cost].checked_add(mass).ok_or(Error::Overflow)?;
+            if with > best[fuel] {
+                best[fuel] = with;
+                taken[index][fuel / 64] |= 1 << (fuel % 64);
+            }
+        }
+    }
+
+    let mut modules = Vec::new();
+    let mut fuel = budget as usize;
+    for index in (0..masses.len()).rev() {
+        if taken[index][fuel / 64] & 1 << (fuel % 64) != 0 {
+            modules.push(index);
+            fuel -= costs[index] as usize;
+        }
+    }
+    modules.reverse();
+    choose(masses, &costs, modules)
+}
+
+fn choose(masses: &[i64], costs: &[i64], modules: Vec<usize>) -> Result<Plan, Error> {
+    let sum = |values: &[i64]| modules.iter().try_fold(0i64, |total, &index| total.checked_add(values[index])).ok_or(Error::Overflow);
+    let mass = sum(masses)?;
+    let fuel = sum(costs)?;
+    Ok(Plan { modules, mass, fuel })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fits_the_most_mass_in_the_budget() {
+        let masses = [12, 14, 1969, 100_756];
+        let formula = Formula::PUZZLE;
+        assert_eq!(plan(&formula, &masses, 1000), Ok(Plan { modules: vec![0, 1, 2], mass: 1995, fuel: 970 }));
+        assert_eq!(plan(&formula, &masses, 969), Ok(Plan { modules: vec![1, 2], mass: 1983, fuel: 968 }));
+        assert_eq!(plan(&formula, &masses, 1), Ok(Plan { modules: vec![], mass: 0, fuel: 0 }));
+        assert_eq!(plan(&formula, &masses, i64::MAX).unwrap().modules, vec![0, 1, 2, 3]);
+        assert_eq!(plan(&formula, &[i64::MAX / 2; 3], i64::MAX), Err(Error::Overflow));
+        let free = Formula::new(i64::MAX, 0, fuel::Rounding::Down).unwrap();
+        assert_eq!(plan(&free, &[i64::MAX - 1, i64::MAX - 1, i64::MAX], 0), Err(Error::Overflow));
+        assert_eq!(plan(&formula, &[12, i64::MAX / 4], MAX_BUDGET + 1), Err(Error::BudgetTooLarge { budget: MAX_BUDGET + 1 }));
+    }
+
+    #[test]
+    fn agrees_with_trying_every_choice() {
+        let masses: Vec<i64> = (0..12).map(|i| 9 + i * 7919 % 613).collect();
+        let formula = Formula::PUZZLE;
+        let costs: Vec<i64> = masses.iter().map(|&mass| formula.fuel_with_fuel(mass).unwrap()).collect();
+        for budget in (0..1000).step_by(37) {
+            let plan = plan(&formula, &masses, budget).unwrap();
+            assert!(plan.fuel <= budget);
+
+            let best = (0..1u32 << masses.len())
+                .map(|set| {
+                    let chosen: Vec<usize> = (0..masses.len()).filter(|&index| set & 1 << index != 0).collect();
+                    choose(&masses, &costs, chosen).unwrap()
+                })
+                .filter(|plan| plan.fuel <= budget)
+                .map(|plan| plan.mass)
+                .max();
+            assert_eq!(Some(plan.mass), best);
+        }
+    }
+}
@@ -1,94 +1,330 @@
+use std::convert::TryFrom;
+use std::{env, fmt, fs, process};
+
 use aoc_lib::AocImplementation;
+use intcode::image;
+use intcode::symbolic::{self, Polynomial};
+
+mod patch;
+
+use patch::{Condition, Search};
 
 fn main() {
-    let day2 = Day2 {};
-    day2.start(2)
+    // The puzzle's search unless another is given, as `--config <file>` or as settings
+    let args: Vec<String> = env::args().skip(1).collect();
+    let search = match args.as_slice() {
+        [] => Ok(Search::puzzle()),
+        [flag, path] if flag == "--config" => match fs::read_to_string(path) {
+            Ok(config) => Search::from_config(&config),
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                process::exit(1);
+            }
+        },
+        args => Search::from_args(args),
+    };
+
+    match search {
+        Ok(search) => Day2 { search }.start(2),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
 }
 
-struct Day2 {}
+struct Day2 {
+    search: Search,
+}
 
-impl AocImplementation<usize> for Day2 {
-    fn process_input(&self, input: &str) -> Vec<usize> {
+impl AocImplementation<i64> for Day2 {
+    // Through an image next to the input, so it's only parsed from text once
+    fn load_input(&self, day: i32) -> Vec<i64> {
+        image::read_cached(aoc_lib::input_file(day)).expect("Failed to read input file")
+    }
+
+    fn process_input(&self, input: &str) -> Vec<i64> {
         input.split(',').map(|n| n.parse().unwrap()).collect()
     }
 
-    fn execute(&self, input: Vec<usize>) -> Option<i32> {
-        let program = input;
-        for noun in 0..=99 {
-            for verb in 0..=99 {
-                let mut temp_program = program.clone();
-                temp_program[1] = noun;
-                temp_program[2] = verb;
+    fn execute(&self, input: Vec<i64>) -> Option<i32> {
+        if let Err(e) = self.search.check(&input) {
+            eprintln!("{}", e);
+            return None;
+        }
 
-                let result = run_intcode(temp_program);
-                if result[0] == 19_690_720 {
-                    return Some((100 * noun + verb) as i32);
-                }
-            }
+        let values = find(&input, &self.search)?;
+        for (patch, value) in self.search.patches.iter().zip(&values) {
+            println!("Cell {}: {}", patch.address, value);
+        }
+        let answer = answer(&values);
+        if answer.is_none() {
+            eprintln!("The values don't combine into one answer");
         }
-        None
+        answer
     }
 }
 
-fn run_intcode(mut program: Vec<usize>) -> Vec<usize> {
-    let end = program.len();
+// The values as digits in base 100, which is 100 * noun + verb for the puzzle. Values that aren't
+// digits, or too many of them, have no answer.
+fn answer(values: &[i64]) -> Option<i32> {
+    let answer = values.iter().try_fold(0i64, |answer, &value| {
+        if !(0..=99).contains(&value) {
+            return None;
+        }
+        answer.checked_mul(100)?.checked_add(value)
+    })?;
+    i32::try_from(answer).ok()
+}
+
+// What the program leaves in the target cell in terms of the patched values, if that doesn't
+// depend on the program taking different paths for different values
+fn formula(program: &[i64], search: &Search) -> Option<Polynomial> {
+    let addresses: Vec<usize> = search.patches.iter().map(|patch| patch.address).collect();
+    let mut memory = symbolic::execute(program, &addresses).ok()?;
+    memory.get_mut(search.target)?.take()
+}
+
+fn solve(formula: &Polynomial, search: &Search, target: i64) -> Option<Vec<i64>> {
+    let ranges: Vec<_> = search.patches.iter().map(|patch| (patch.address, patch.values.clone())).collect();
+    symbolic::solve(formula, target, &ranges)
+}
 
-    for i in (0..end).step_by(4) {
+// Solving is quicker than searching, but the symbolic run wraps around and reads past the end of
+// the program where running it for real fails. Solved values that fail that way are searched past.
+fn find(program: &[i64], search: &Search) -> Option<Vec<i64>> {
+    if let (Some(formula), Condition::Equals(target)) = (formula(program, search), search.condition) {
+        let solved = solve(&formula, search, target).filter(|values| hits(program, search, values));
+        if solved.is_some() {
+            return solved;
+        }
+    }
+    self::search(program, search)
+}
+
+// Values that make the program fail are skipped, like ones that miss the target
+fn search(program: &[i64], search: &Search) -> Option<Vec<i64>> {
+    search.combinations().find(|values| hits(program, search, values))
+}
+
+fn hits(program: &[i64], search: &Search, values: &[i64]) -> bool {
+    let mut temp_program = program.to_vec();
+    search.apply(&mut temp_program, values);
+
+    let result = run_intcode(temp_program);
+    result.is_ok_and(|result| search.condition.holds(result[search.target]))
+}
+
+// Opcodes with how many parameters follow them
+const INSTRUCTIONS: &[(i64, usize)] = &[(1, 3), (2, 3), (99, 0)];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Error {
+    UnknownOpcode { address: usize, opcode: i64 },
+    // The instruction at `address` goes past the end of the program
+    Truncated { address: usize },
+    InvalidAddress { address: usize, target: i64 },
+    Overflow { address: usize },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnknownOpcode { address, opcode } => write!(f, "unknown opcode {} at {}", opcode, address),
+            Error::Truncated { address } => write!(f, "the instruction at {} is cut off", address),
+            Error::InvalidAddress { address, target } => write!(f, "the instruction at {} uses invalid address {}", address, target),
+            Error::Overflow { address } => write!(f, "the instruction at {} overflows", address),
+        }
+    }
+}
+
+// Runs until halting or running off the end of the program. This decides which values fail, for
+// solved values as much as searched ones.
+fn run_intcode(mut program: Vec<i64>) -> Result<Vec<i64>, Error> {
+    let mut i = 0;
+
+    while i < program.len() {
         let op = program[i];
-//        println!("op: {}, i: {}", op, i);
-        match op {
-            1 => {
-                let pos1 = program[i + 1];
-                let pos2 = program[i + 2];
-                let out_pos = program[i + 3];
-                let left = program[pos1];
-                let right = program[pos2];
-                let sum = left + right;
-                program[out_pos] = sum;
+        let parameter_count = match INSTRUCTIONS.iter().find(|(opcode, _)| *opcode == op) {
+            Some(&(_, count)) => count,
+            None => return Err(Error::UnknownOpcode { address: i, opcode: op }),
+        };
+        let parameters = program.get(i + 1..i + 1 + parameter_count).ok_or(Error::Truncated { address: i })?.to_vec();
+        let position = |target: i64| {
+            if target < 0 || target as usize >= program.len() {
+                Err(Error::InvalidAddress { address: i, target })
+            } else {
+                Ok(target as usize)
             }
-            2 => {
-                let pos1 = program[i + 1];
-                let pos2 = program[i + 2];
-                let out_pos = program[i + 3];
+        };
+
+        match op {
+            1 | 2 => {
+                let pos1 = position(parameters[0])?;
+                let pos2 = position(parameters[1])?;
+                let out_pos = position(parameters[2])?;
                 let left = program[pos1];
                 let right = program[pos2];
-                let product = left * right;
-                program[out_pos] = product;
+                let result = if op == 1 { left.checked_add(right) } else { left.checked_mul(right) };
+                program[out_pos] = result.ok_or(Error::Overflow { address: i })?;
             }
             // terminal
-            99 => return program,
-            _ => panic!("Unexpected opcode {}", op)
+            99 => return Ok(program),
+            _ => unreachable!(),
         }
+        i += 1 + parameter_count;
     }
 
-    program
+    Ok(program)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use intcode::fuzz::{self, Generator, Rng};
+    use intcode::{Limits, Mode, Opcode};
 
     #[test]
     fn example1() {
         let result = run_intcode(vec![1, 0, 0, 0, 99]);
-        assert_eq!(result, vec![2, 0, 0, 0, 99]);
+        assert_eq!(result, Ok(vec![2, 0, 0, 0, 99]));
     }
 
     #[test]
     fn example2() {
         let result = run_intcode(vec![2, 3, 0, 3, 99]);
-        assert_eq!(result, vec![2, 3, 0, 6, 99]);
+        assert_eq!(result, Ok(vec![2, 3, 0, 6, 99]));
     }
 
     #[test]
     fn example3() {
         let result = run_intcode(vec![2, 4, 4, 5, 99, 0]);
-        assert_eq!(result, vec![2, 4, 4, 5, 99, 9801]);
+        assert_eq!(result, Ok(vec![2, 4, 4, 5, 99, 9801]));
     }
 
     #[test]
     fn example4() {
         let result = run_intcode(vec![1, 1, 1, 4, 99, 5, 6, 0, 99]);
-        assert_eq!(result, vec![30, 1, 1, 4, 2, 5, 6, 0, 99]);
+        assert_eq!(result, Ok(vec![30, 1, 1, 4, 2, 5, 6, 0, 99]));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn fails_on_invalid_programs() {
+        assert_eq!(run_intcode(vec![1, 0, 0, 0, 7, 99]), Err(Error::UnknownOpcode { address: 4, opcode: 7 }));
+        assert_eq!(run_intcode(vec![1, 0, 0, 5, 99]), Err(Error::InvalidAddress { address: 0, target: 5 }));
+        assert_eq!(run_intcode(vec![2, 0, -1, 0, 99]), Err(Error::InvalidAddress { address: 0, target: -1 }));
+        assert_eq!(run_intcode(vec![1, 0, 0, 0, 1, 0]), Err(Error::Truncated { address: 4 }));
+
+        // Squaring 2 five times is 2^32, and once more doesn't fit
+        let mut squares: Vec<i64> = [2, 0, 0, 0].iter().copied().cycle().take(4 * 6).collect();
+        squares.push(99);
+        assert_eq!(run_intcode(squares), Err(Error::Overflow { address: 20 }));
+    }
+
+    #[test]
+    fn works_with_negative_values() {
+        // Halting takes a single cell, so data can follow it anywhere
+        let result = run_intcode(vec![1, 6, 7, 0, 99, 5, -7, 3]);
+        assert_eq!(result, Ok(vec![-4, 6, 7, 0, 99, 5, -7, 3]));
+    }
+
+    #[test]
+    fn combines_values_into_an_answer() {
+        assert_eq!(answer(&[12, 2]), Some(1202));
+        assert_eq!(answer(&[5]), Some(5));
+        assert_eq!(answer(&[]), Some(0));
+        assert_eq!(answer(&[100, 2]), None);
+        assert_eq!(answer(&[-1, 2]), None);
+        assert_eq!(answer(&[21, 47, 48, 36, 47]), Some(2_147_483_647));
+        assert_eq!(answer(&[21, 47, 48, 36, 48]), None);
+        assert_eq!(answer(&[99; 10]), None);
+    }
+
+    #[test]
+    fn skips_programs_that_fail() {
+        // Cells past 4 don't exist, so every verb above 4 fails
+        let program = vec![1, 0, 0, 0, 99];
+        let sums = Search::new(0, Condition::Equals(198)).patch(1, 3..=9).patch(2, 3..=9);
+        assert_eq!(search(&program, &sums), Some(vec![4, 4]));
+    }
+
+    #[test]
+    fn skips_solutions_that_fail() {
+        // Only a verb below 14 is a real address, so the solved 0 and 20 can't run
+        let program = vec![1, 0, 0, 3, 1, 1, 2, 3, 2, 3, 13, 0, 99, 11];
+        let formula = formula(&program, &noun_and_verb(220)).unwrap();
+        assert_eq!(solve(&formula, &noun_and_verb(220), 220), Some(vec![0, 20]));
+        assert!(!hits(&program, &noun_and_verb(220), &[0, 20]));
+        assert_eq!(find(&program, &noun_and_verb(220)), Some(vec![7, 13]));
+    }
+
+    fn padded(mut program: Vec<i64>) -> Vec<i64> {
+        program.resize(100, 0);
+        program
+    }
+
+    fn noun_and_verb(target: i64) -> Search {
+        Search::new(0, Condition::Equals(target)).patch(1, 0..=99).patch(2, 0..=99)
+    }
+
+    #[test]
+    fn solves_for_noun_and_verb() {
+        // Cell 3 is first set through the noun and verb as addresses, then overwritten
+        let program = padded(vec![1, 0, 0, 3, 1, 1, 2, 3, 2, 3, 13, 0, 99, 11]);
+        let formula = formula(&program, &noun_and_verb(792)).unwrap();
+        assert_eq!(formula.to_string(), "11*[1] + 11*[2]");
+        assert_eq!(solve(&formula, &noun_and_verb(792), 792), Some(vec![0, 72]));
+        assert_eq!(solve(&formula, &noun_and_verb(792), 792), search(&program, &noun_and_verb(792)));
+        assert_eq!(solve(&formula, &noun_and_verb(793), 793), None);
+    }
+
+    #[test]
+    fn agrees_with_the_search_without_padding() {
+        // The noun and verb are read as addresses, and only 14 of them exist
+        let program = vec![1, 0, 0, 3, 1, 1, 2, 3, 2, 3, 13, 0, 99, 11];
+        let formula = formula(&program, &noun_and_verb(792)).unwrap();
+        assert_eq!(solve(&formula, &noun_and_verb(792), 792), Some(vec![0, 72]));
+        assert_eq!(search(&program, &noun_and_verb(792)), None);
+        for target in &[143, 792] {
+            assert_eq!(find(&program, &noun_and_verb(*target)), search(&program, &noun_and_verb(*target)), "target {}", target);
+        }
+        assert_eq!(find(&program, &noun_and_verb(143)), Some(vec![0, 13]));
+    }
+
+    #[test]
+    fn falls_back_to_search() {
+        let program = padded(vec![1, 0, 0, 0, 99]);
+        assert_eq!(formula(&program, &noun_and_verb(100)), None);
+        assert_eq!(search(&program, &noun_and_verb(100)), Some(vec![0, 4]));
+        assert_eq!(find(&program, &noun_and_verb(100)), Some(vec![0, 4]));
+    }
+
+    #[test]
+    fn searches_other_cells() {
+        // Cell 7 gets the sum of cells 5 and 6
+        let program = vec![1, 5, 6, 7, 99, 0, 0, 0];
+        let sums = Search::new(7, Condition::Equals(9)).patch(5, 2..=9).patch(6, 0..=3);
+        assert_eq!(formula(&program, &sums).map(|formula| formula.to_string()), Some("[5] + [6]".to_string()));
+        assert_eq!(search(&program, &sums), Some(vec![6, 3]));
+        assert_eq!(solve(&formula(&program, &sums).unwrap(), &sums, 9), Some(vec![6, 3]));
+
+        let larger = Search::new(7, Condition::Greater(10)).patch(5, 2..=9).patch(6, 0..=3);
+        assert_eq!(search(&program, &larger), Some(vec![8, 3]));
+        let not_zero = Search::new(7, Condition::NotEquals(0)).patch(6, 0..=3);
+        assert_eq!(search(&program, &not_zero), Some(vec![1]));
+    }
+
+    #[test]
+    fn matches_the_shared_machine() {
+        // Only adds and multiplies in position mode, the rest isn't supported here
+        let generator = Generator::new(&[Opcode::Add, Opcode::Multiply]).modes(&[Mode::Position]).values(0..=50);
+        for seed in 0..500 {
+            let program = generator.generate(&mut Rng::new(seed));
+            let run = fuzz::run(&program, &[], Limits::default());
+            if run.largest > i32::MAX as u64 {
+                continue;
+            }
+            assert_eq!(run_intcode(program), Ok(run.memory), "seed {}", seed);
+        }
+    }
+}
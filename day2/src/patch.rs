@@ -0,0 +1,274 @@
+// Which cells to patch before running a program, the values to try in each, and what the program
+// has to leave in a target cell. The puzzle patches the noun and verb into cells 1 and 2 and
+// wants 19690720 in cell 0, but other programs can be searched the same way.
+//
+// Searches are given as arguments or as a config file with one setting a line:
+//
+//     # The puzzle
+//     patch 1=0..=99
+//     patch 2=0..=99
+//     target 0==19690720
+//
+// Targets compare with one of ==, !=, <, <=, > and >=.
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    Invalid(String),
+    NoTarget,
+    AddressOutOfRange { address: usize },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Invalid(message) => write!(f, "{}", message),
+            Error::NoTarget => write!(f, "no target was given"),
+            Error::AddressOutOfRange { address } => write!(f, "address {} is outside the program", address),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Patch {
+    pub address: usize,
+    pub values: RangeInclusive<i64>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Condition {
+    Equals(i64),
+    NotEquals(i64),
+    Less(i64),
+    LessOrEqual(i64),
+    Greater(i64),
+    GreaterOrEqual(i64),
+}
+
+impl Condition {
+    pub fn holds(&self, value: i64) -> bool {
+        match *self {
+            Condition::Equals(target) => value == target,
+            Condition::NotEquals(target) => value != target,
+            Condition::Less(target) => value < target,
+            Condition::LessOrEqual(target) => value <= target,
+            Condition::Greater(target) => value > target,
+            Condition::GreaterOrEqual(target) => value >= target,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Search {
+    pub patches: Vec<Patch>,
+    pub target: usize,
+    pub condition: Condition,
+}
+
+impl Search {
+    pub fn new(target: usize, condition: Condition) -> Search {
+        Search { patches: Vec::new(), target, condition }
+    }
+
+    pub fn puzzle() -> Search {
+        Search::new(0, Condition::Equals(19_690_720)).patch(1, 0..=99).patch(2, 0..=99)
+    }
+
+    pub fn patch(mut self, address: usize, values: RangeInclusive<i64>) -> Self {
+        self.patches.push(Patch { address, values });
+        self
+    }
+
+    // Pairs of `--patch <address>=<values>` and `--target <address><comparison><value>`
+    pub fn from_args(args: &[String]) -> Result<Search, Error> {
+        let mut settings = Settings::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let value = args.next().ok_or_else(|| Error::Invalid(format!("{} needs a value", arg)))?;
+            settings.set(arg.trim_start_matches("--"), value)?;
+        }
+        settings.search()
+    }
+
+    pub fn from_config(config: &str) -> Result<Search, Error> {
+        let mut settings = Settings::default();
+        for (index, line) in config.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = |message: String| Error::Invalid(format!("line {}: {}", index + 1, message));
+            let (name, value) = line.split_once(char::is_whitespace).ok_or_else(|| invalid(format!("{} needs a value", line)))?;
+            settings.set(name, value.trim()).map_err(|error| match error {
+                Error::Invalid(message) => invalid(message),
+                error => error,
+            })?;
+        }
+        settings.search()
+    }
+
+    // Every patch has to land inside the program, as does the target
+    pub fn check(&self, program: &[i64]) -> Result<(), Error> {
+        let mut addresses = self.patches.iter().map(|patch| patch.address).chain(Some(self.target));
+        match addresses.find(|&address| address >= program.len()) {
+            Some(address) => Err(Error::AddressOutOfRange { address }),
+            None => Ok(()),
+        }
+    }
+
+    pub fn apply(&self, program: &mut [i64], values: &[i64]) {
+        for (patch, &value) in self.patches.iter().zip(values) {
+            program[patch.address] = value;
+        }
+    }
+
+    // Every combination of values, counting up in the last patch first like nested loops would
+    pub fn combinations(&self) -> impl Iterator<Item = Vec<i64>> + '_ {
+        let mut next = if self.patches.iter().any(|patch| patch.values.is_empty()) {
+            None
+        } else {
+            Some(self.patches.iter().map(|patch| *patch.values.start()).collect::<Vec<_>>())
+        };
+
+        std::iter::from_fn(move || {
+            let current = next.take()?;
+            let mut following = current.clone();
+            for (index, patch) in self.patches.iter().enumerate().rev() {
+                if following[index] < *patch.values.end() {
+                    following[index] += 1;
+                    next = Some(following);
+                    break;
+                }
+                following[index] = *patch.values.start();
+            }
+            Some(current)
+        })
+    }
+}
+
+#[derive(Default)]
+struct Settings {
+    patches: Vec<Patch>,
+    target: Option<(usize, Condition)>,
+}
+
+impl Settings {
+    fn set(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        match name {
+            "patch" => self.patches.push(parse_patch(value)?),
+            "target" => self.target = Some(parse_target(value)?),
+            _ => return Err(Error::Invalid(format!("unknown setting {}", name))),
+        }
+        Ok(())
+    }
+
+    fn search(self) -> Result<Search, Error> {
+        let (target, condition) = self.target.ok_or(Error::NoTarget)?;
+        Ok(Search { patches: self.patches, target, condition })
+    }
+}
+
+// `1=0..=99`, `1=0..100` or `1=5`
+fn parse_patch(text: &str) -> Result<Patch, Error> {
+    let (address, values) = text.split_once('=').ok_or_else(|| Error::Invalid(format!("{} isn't <address>=<values>", text)))?;
+    let address = parse_address(address)?;
+    let values = if let Some((start, end)) = values.split_once("..=") {
+        parse_value(start)?..=parse_value(end)?
+    } else if let Some((start, end)) = values.split_once("..") {
+        let end = parse_value(end)?.checked_sub(1).ok_or_else(|| Error::Invalid(format!("{} is empty", values)))?;
+        parse_value(start)?..=end
+    } else {
+        let value = parse_value(values)?;
+        value..=value
+    };
+    Ok(Patch { address, values })
+}
+
+// `0==19690720`, `0>=100` and so on
+fn parse_target(text: &str) -> Result<(usize, Condition), Error> {
+    let start = text.find(|c| "=!<>".contains(c)).ok_or_else(|| Error::Invalid(format!("{} has no comparison", text)))?;
+    let end = text[start..].find(|c: char| !"=!<>".contains(c)).map_or(text.len(), |length| start + length);
+    let address = parse_address(&text[..start])?;
+    let value = parse_value(&text[end..])?;
+    let condition = match &text[start..end] {
+        "==" => Condition::Equals(value),
+        "!=" => Condition::NotEquals(value),
+        "<" => Condition::Less(value),
+        "<=" => Condition::LessOrEqual(value),
+        ">" => Condition::Greater(value),
+        ">=" => Condition::GreaterOrEqual(value),
+        comparison => return Err(Error::Invalid(format!("unknown comparison {}", comparison))),
+    };
+    Ok((address, condition))
+}
+
+fn parse_address(text: &str) -> Result<usize, Error> {
+    parse_number(text).map_err(|_| Error::Invalid(format!("{} isn't an address", text.trim())))
+}
+
+fn parse_value(text: &str) -> Result<i64, Error> {
+    parse_number(text)
+}
+
+fn parse_number<T: FromStr>(text: &str) -> Result<T, Error> {
+    let text = text.trim();
+    text.replace('_', "").parse().map_err(|_| Error::Invalid(format!("{} isn't a number", text)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(text: &str) -> Vec<String> {
+        text.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn reads_searches() {
+        let search = Search::from_args(&args("--patch 1=0..=99 --patch 2=0..100 --target 0==19_690_720")).unwrap();
+        assert_eq!(search.patches, Search::puzzle().patches);
+        assert_eq!((search.target, search.condition), (0, Condition::Equals(19_690_720)));
+
+        let config = "# Only the verb\npatch 2=5\n\ntarget 3 >= 10  # at least\n";
+        let search = Search::from_config(config).unwrap();
+        assert_eq!(search.patches, vec![Patch { address: 2, values: 5..=5 }]);
+        assert_eq!((search.target, search.condition), (3, Condition::GreaterOrEqual(10)));
+
+        let search = Search::from_config("patch 1=-5..5\ntarget 0>-3").unwrap();
+        assert_eq!(search.patches, vec![Patch { address: 1, values: -5..=4 }]);
+        assert_eq!(search.condition, Condition::Greater(-3));
+    }
+
+    #[test]
+    fn rejects_bad_settings() {
+        assert_eq!(Search::from_args(&args("--patch 1=0..=99")).unwrap_err(), Error::NoTarget);
+        assert_eq!(Search::from_args(&args("--patch")).unwrap_err(), Error::Invalid("--patch needs a value".to_string()));
+        assert_eq!(Search::from_config("target 0=>5").unwrap_err(), Error::Invalid("line 1: unknown comparison =>".to_string()));
+        assert_eq!(Search::from_config("\npatch 1..2").unwrap_err(), Error::Invalid("line 2: 1..2 isn't <address>=<values>".to_string()));
+        assert!(Search::from_config("patch 1=0..-9223372036854775808\ntarget 0==1").is_err());
+        assert!(Search::from_config("patch -1=0\ntarget 0==1").is_err());
+        assert!(Search::from_config("limit 5").is_err());
+
+        let search = Search::puzzle();
+        assert_eq!(search.check(&[1, 0, 0, 0, 99]), Ok(()));
+        assert_eq!(search.check(&[99, 0]), Err(Error::AddressOutOfRange { address: 2 }));
+    }
+
+    #[test]
+    fn counts_through_the_combinations() {
+        let search = Search::new(0, Condition::LessOrEqual(4)).patch(1, 3..=4).patch(2, 0..=2);
+        let combinations: Vec<Vec<i64>> = search.combinations().collect();
+        assert_eq!(combinations, vec![vec![3, 0], vec![3, 1], vec![3, 2], vec![4, 0], vec![4, 1], vec![4, 2]]);
+        assert!(search.condition.holds(4) && !search.condition.holds(5));
+        assert!(Condition::NotEquals(4).holds(5) && !Condition::Less(4).holds(4));
+
+        assert_eq!(Search::new(0, Condition::Equals(0)).combinations().count(), 1);
+        #[allow(clippy::reversed_empty_ranges)]
+        let empty = Search::new(0, Condition::Equals(0)).patch(1, 5..=4);
+        assert_eq!(empty.combinations().count(), 0);
+    }
+}
@@ -1,4 +1,6 @@
 use aoc_lib::AocImplementation;
+use intcode::image;
+use std::convert::TryFrom;
 
 fn main() {
     let day2 = Day5 {
@@ -12,6 +14,12 @@ struct Day5 {
 }
 
 impl AocImplementation<i32> for Day5 {
+    // Through an image next to the input, so it's only parsed from text once
+    fn load_input(&self, day: i32) -> Vec<i32> {
+        let program = image::read_cached(aoc_lib::input_file(day)).expect("Failed to read input file");
+        program.into_iter().map(|n| i32::try_from(n).unwrap_or_else(|_| panic!("Number too big: {}", n))).collect()
+    }
+
     fn process_input(&self, input: &str) -> Vec<i32> {
         input.split(',').map(|n| n.parse().unwrap_or_else(|_| panic!("Failed to parse number: {}", n))).collect()
     }
@@ -32,10 +40,10 @@ enum Parameter {
 }
 
 impl Parameter {
-    fn parse(mode: &str, value: &i32, index: &i32) -> Parameter {
+    fn parse(mode: i32, value: &i32, index: &i32) -> Parameter {
         match mode {
-            "0" => Parameter::Position(*value),
-            "1" => Parameter::Immediate(*index),
+            0 => Parameter::Position(*value),
+            1 => Parameter::Immediate(*index),
             _ => panic!("Unexpected parameter mode: {}", mode)
         }
     }
@@ -85,12 +93,9 @@ impl Instruction {
     fn parse(index: &i32, program: &Vec<i32>) -> Instruction {
         let op = program[*index as usize];
 
-        let op_parts = format!("{:05}", op);
-
-        let op_code = &op_parts[3..5];
-        match op_code {
-            "01" => {
-                let params = Instruction::parse_parameters(3, &op_parts[..], program, index);
+        match op % 100 {
+            1 => {
+                let params = Instruction::parse_parameters(3, op, program, index);
 
                 let left = params[0].to_owned();
                 let right = params[1].to_owned();
@@ -98,8 +103,8 @@ impl Instruction {
 
                 Instruction::Add { left, right, out }
             }
-            "02" => {
-                let params = Instruction::parse_parameters(3, &op_parts[..], program, index);
+            2 => {
+                let params = Instruction::parse_parameters(3, op, program, index);
 
                 let left = params[0].to_owned();
                 let right = params[1].to_owned();
@@ -107,38 +112,38 @@ impl Instruction {
 
                 Instruction::Multiply { left, right, out }
             }
-            "03" => {
-                let params = Instruction::parse_parameters(1, &op_parts[..], program, index);
+            3 => {
+                let params = Instruction::parse_parameters(1, op, program, index);
 
                 let out = params[0].unwrap_inner();
 
                 Instruction::Input { out }
             }
-            "04" => {
-                let params = Instruction::parse_parameters(1, &op_parts[..], program, index);
+            4 => {
+                let params = Instruction::parse_parameters(1, op, program, index);
 
                 let p = params[0].to_owned();
 
                 Instruction::Output { p }
             }
-            "05" => {
-                let params = Instruction::parse_parameters(2, &op_parts[..], program, index);
+            5 => {
+                let params = Instruction::parse_parameters(2, op, program, index);
 
                 let value = params[0].to_owned();
                 let target = params[1].to_owned();
 
                 Instruction::JumpIfTrue { value, target }
             }
-            "06" => {
-                let params = Instruction::parse_parameters(2, &op_parts[..], program, index);
+            6 => {
+                let params = Instruction::parse_parameters(2, op, program, index);
 
                 let value = params[0].to_owned();
                 let target = params[1].to_owned();
 
                 Instruction::JumpIfFalse { value, target }
             }
-            "07" => {
-                let params = Instruction::parse_parameters(3, &op_parts[..], program, index);
+            7 => {
+                let params = Instruction::parse_parameters(3, op, program, index);
 
                 let first = params[0].to_owned();
                 let second = params[1].to_owned();
@@ -146,8 +151,8 @@ impl Instruction {
 
                 Instruction::LessThan { first, second, out }
             }
-            "08" => {
-                let params = Instruction::parse_parameters(3, &op_parts[..], program, index);
+            8 => {
+                let params = Instruction::parse_parameters(3, op, program, index);
 
                 let first = params[0].to_owned();
                 let second = params[1].to_owned();
@@ -155,11 +160,11 @@ impl Instruction {
 
                 Instruction::Equals { first, second, out }
             }
-            "99" => {
+            99 => {
                 Instruction::Terminate
             }
 
-            _ => panic!("Unexpected instruction: {}", op_parts),
+            _ => panic!("Unexpected instruction: {}", op),
         }
     }
 
@@ -177,18 +182,16 @@ impl Instruction {
         }
     }
 
-    fn parse_parameters(number: i32, op_parts: &str, program: &Vec<i32>, instruction_pointer: &i32) -> Vec<Parameter> {
-        let mut p = Vec::new();
+    fn parse_parameters(number: i32, op: i32, program: &Vec<i32>, instruction_pointer: &i32) -> [Parameter; 3] {
+        let mut p = [Parameter::Immediate(0), Parameter::Immediate(0), Parameter::Immediate(0)];
 
-        for i in (0..3).rev().take(number as usize) {
-            let idx = i as usize;
-            let mode = &op_parts[idx..=idx];
-            println!("Loading mode {} for idx {}", mode, idx);
-            let total_idx = (3 - i - 1) + *instruction_pointer + 1;
+        let mut modes = op / 100;
+        for i in 0..number {
+            let total_idx = *instruction_pointer + i + 1;
             let value = program[total_idx as usize];
 
-            let param = Parameter::parse(mode, &value, &total_idx);
-            p.push(param)
+            p[i as usize] = Parameter::parse(modes % 10, &value, &total_idx);
+            modes /= 10;
         }
 
         p
@@ -196,7 +199,6 @@ impl Instruction {
 
     // true if the program should continue
     fn execute(&self, program: &mut Vec<i32>, input: &i32, outputs: &mut Vec<i32>) -> InstructionResult {
-        println!("Executing instruction: {:#?}", self);
         match self {
             Instruction::Add { left, right, out } => {
                 program[*out as usize] = left.get_value(program) + right.get_value(program);
@@ -238,7 +240,6 @@ impl Instruction {
             }
             Instruction::JumpIfTrue { value, target } => {
                 let v = value.get_value(program);
-                println!("Checking if {} is true", v);
                 if v != 0 {
                     InstructionResult::GoTo(target.get_value(program))
                 } else {
@@ -247,7 +248,6 @@ impl Instruction {
             }
             Instruction::JumpIfFalse { value, target } => {
                 let v = value.get_value(program);
-                println!("Checking if {} is false", v);
                 if v == 0 {
                     InstructionResult::GoTo(target.get_value(program))
                 } else {
@@ -276,11 +276,8 @@ fn run_intcode(mut program: Vec<i32>, input: i32) -> ExecutionResult {
 
     let mut i = 0;
     while i < end {
-        let op = program[i as usize];
         let instruction = Instruction::parse(&i, &program);
-        println!("Index: {}, op: {}, state: {:#?}", i, op, program);
         let result = instruction.execute(&mut program, &input, &mut outputs);
-        println!("Result: {:#?}", result);
         match result {
             InstructionResult::Halt => return ExecutionResult { program, outputs },
             InstructionResult::Continue(by) => i += by,
@@ -297,6 +294,8 @@ fn run_intcode(mut program: Vec<i32>, input: i32) -> ExecutionResult {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use intcode::fuzz::{self, Generator, Rng};
+    use intcode::Limits;
 
     #[test]
     fn example1() {
@@ -455,10 +454,29 @@ mod tests {
 
     #[test]
     fn gets_parameters() {
-        let opcode = "01105";
+        let opcode = 1105;
 
         let program = vec![1105, 2, 4];
         let params = Instruction::parse_parameters(2, opcode, &program, &0);
-        assert_eq!(params, vec![Parameter::parse("1", &2, &1), Parameter::parse("1", &4, &2)]);
+        assert_eq!(params[..2], [Parameter::parse(1, &2, &1), Parameter::parse(1, &4, &2)]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn matches_the_shared_machine() {
+        let generator = Generator::new(&intcode::OPCODES);
+        for seed in 0..500 {
+            let mut rng = Rng::new(seed);
+            let program = generator.generate(&mut rng);
+            let input = rng.range(-100..=100);
+
+            // Every input instruction reads the same value here
+            let run = fuzz::run(&program, &vec![input; program.len()], Limits::default());
+            if run.largest > i32::MAX as u64 {
+                continue;
+            }
+            let result = run_intcode(program.iter().map(|&cell| cell as i32).collect(), input as i32);
+            assert_eq!(result.program.iter().map(|&cell| cell as i64).collect::<Vec<_>>(), run.memory, "seed {}", seed);
+            assert_eq!(result.outputs.iter().map(|&cell| cell as i64).collect::<Vec<_>>(), run.outputs, "seed {}", seed);
+        }
+    }
+}
@@ -1,26 +1,125 @@
 use aoc_lib::AocImplementation;
+use intcode::image;
+use std::convert::TryFrom;
 use std::collections::VecDeque;
-use itertools::Itertools;
+use std::{env, fs, process};
 use std::ops::Range;
 
+mod search;
+mod topology;
+
+use search::{find_max_signal, find_top_signals};
+use topology::{Execution, Topology};
+
 fn main() {
-    let day7 = Day7 {
-        program_input: 5
+    let mut day7 = Day7 {
+        program_input: 5,
+        mode: Mode::Feedback,
+        topology: None,
+        sequence: None,
+        execution: Execution::Threads,
+        top: None,
+        trace: None,
     };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--serial" => day7.mode = Mode::Serial,
+            "--feedback" => day7.mode = Mode::Feedback,
+            "--topology" => {
+                let description = args.next().unwrap_or_else(|| usage());
+                match Topology::parse(&description) {
+                    Ok(topology) => day7.topology = Some(topology),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        process::exit(2);
+                    }
+                }
+            }
+            "--phases" => {
+                let phases = args.next().and_then(|phases| phases.split(',').map(|phase| phase.trim().parse().ok()).collect());
+                day7.sequence = Some(phases.unwrap_or_else(|| usage()));
+            }
+            "--turns" => day7.execution = Execution::Turns,
+            "--threads" => day7.execution = Execution::Threads,
+            "--top" => day7.top = Some(args.next().and_then(|count| count.parse().ok()).unwrap_or_else(|| usage())),
+            "--trace" => day7.trace = Some(args.next().unwrap_or_else(|| usage())),
+            _ => usage(),
+        }
+    }
     day7.start(7)
 }
 
+fn usage() -> ! {
+    eprintln!("Usage: day7 [options]");
+    eprintln!("    --serial            Search phases 0 to 4 with the amplifiers in a chain, like part one");
+    eprintln!("    --feedback          Search phases 5 to 9 with a feedback loop, like part two and the default");
+    eprintln!("    --topology <wires>  Wire the amplifiers like \"A>B,A>C,B>C>\" instead, searching the mode's phases");
+    eprintln!("    --phases <phases>   Run the amplifiers once with these phases, like \"9,8,7,6,5\", instead of searching");
+    eprintln!("    --turns             Run those amplifiers taking turns on one thread");
+    eprintln!("    --threads           Run those amplifiers on a thread each, the default");
+    eprintln!("    --top <count>       Print the sequences with the highest signals");
+    eprintln!("    --trace <file>      Write every value passed with the best sequence to a CSV file");
+    process::exit(2)
+}
+
 struct Day7 {
-    program_input: i32
+    program_input: i32,
+    mode: Mode,
+    // Wires the amplifiers instead of the mode
+    topology: Option<Topology>,
+    // The phases to run with, instead of searching for the best ones
+    sequence: Option<Vec<i32>>,
+    // How to run the amplifiers for a single sequence. Searches already run sequences on every
+    // core, so their amplifiers always take turns.
+    execution: Execution,
+    top: Option<usize>,
+    // Where to write the trace of the best sequence
+    trace: Option<String>,
 }
 
 impl AocImplementation<i32> for Day7 {
+    // Through an image next to the input, so it's only parsed from text once
+    fn load_input(&self, day: i32) -> Vec<i32> {
+        let program = image::read_cached(aoc_lib::input_file(day)).expect("Failed to read input file");
+        program.into_iter().map(|n| i32::try_from(n).unwrap_or_else(|_| panic!("Number too big: {}", n))).collect()
+    }
+
     fn process_input(&self, input: &str) -> Vec<i32> {
         input.split(',').map(|n| n.parse().unwrap_or_else(|_| panic!("Failed to parse number: {}", n))).collect()
     }
 
     fn execute(&self, program: Vec<i32>) -> Option<i32> {
-        let result = find_max_phase_signal(program, (5..=9).collect());
+        let phases = self.mode.phases();
+        let topology = self.topology.clone().unwrap_or_else(|| self.mode.topology(phases.len()));
+        let result = if let Some(sequence) = &self.sequence {
+            if sequence.len() != topology.len() {
+                eprintln!("There are {} amplifiers but {} phases", topology.len(), sequence.len());
+                return None;
+            }
+            let signal = topology.run_with(self.execution, &program, sequence)?;
+            PhaseResult { sequence: sequence.clone(), signal }
+        } else if topology.len() > phases.len() {
+            eprintln!("There are {} amplifiers but only {} phases", topology.len(), phases.len());
+            return None;
+        } else if let Some(count) = self.top {
+            let top = find_top_signals(&program, &phases, &topology, count);
+            for (rank, result) in top.iter().enumerate() {
+                println!("{}. {} from {:?}", rank + 1, result.signal, result.sequence);
+            }
+            top.into_iter().next()?
+        } else {
+            find_max_signal(&program, &phases, &topology)
+        };
+
+        if let Some(path) = &self.trace {
+            let trace = topology.trace(&program, &result.sequence);
+            match fs::write(path, trace.to_csv()) {
+                Ok(()) => println!("Traced {} values ending in {:?} to {}", trace.transfers.len(), trace.signal(), path),
+                Err(e) => eprintln!("Failed to write {}: {}", path, e),
+            }
+        }
         Some(result.signal)
     }
 }
@@ -32,10 +131,10 @@ enum Parameter {
 }
 
 impl Parameter {
-    fn parse(mode: &str, value: &i32, index: &i32) -> Parameter {
+    fn parse(mode: i32, value: &i32, index: &i32) -> Parameter {
         match mode {
-            "0" => Parameter::Position(*value),
-            "1" => Parameter::Immediate(*index),
+            0 => Parameter::Position(*value),
+            1 => Parameter::Immediate(*index),
             _ => panic!("Unexpected parameter mode: {}", mode)
         }
     }
@@ -85,12 +184,9 @@ impl Instruction {
     fn parse(index: &i32, program: &Vec<i32>) -> Instruction {
         let op = program[*index as usize];
 
-        let op_parts = format!("{:05}", op);
-
-        let op_code = &op_parts[3..5];
-        match op_code {
-            "01" => {
-                let params = Instruction::parse_parameters(3, &op_parts[..], program, index);
+        match op % 100 {
+            1 => {
+                let params = Instruction::parse_parameters(3, op, program, index);
 
                 let left = params[0].to_owned();
                 let right = params[1].to_owned();
@@ -98,8 +194,8 @@ impl Instruction {
 
                 Instruction::Add { left, right, out }
             }
-            "02" => {
-                let params = Instruction::parse_parameters(3, &op_parts[..], program, index);
+            2 => {
+                let params = Instruction::parse_parameters(3, op, program, index);
 
                 let left = params[0].to_owned();
                 let right = params[1].to_owned();
@@ -107,38 +203,38 @@ impl Instruction {
 
                 Instruction::Multiply { left, right, out }
             }
-            "03" => {
-                let params = Instruction::parse_parameters(1, &op_parts[..], program, index);
+            3 => {
+                let params = Instruction::parse_parameters(1, op, program, index);
 
                 let out = params[0].unwrap_inner();
 
                 Instruction::Input { out }
             }
-            "04" => {
-                let params = Instruction::parse_parameters(1, &op_parts[..], program, index);
+            4 => {
+                let params = Instruction::parse_parameters(1, op, program, index);
 
                 let p = params[0].to_owned();
 
                 Instruction::Output { p }
             }
-            "05" => {
-                let params = Instruction::parse_parameters(2, &op_parts[..], program, index);
+            5 => {
+                let params = Instruction::parse_parameters(2, op, program, index);
 
                 let value = params[0].to_owned();
                 let target = params[1].to_owned();
 
                 Instruction::JumpIfTrue { value, target }
             }
-            "06" => {
-                let params = Instruction::parse_parameters(2, &op_parts[..], program, index);
+            6 => {
+                let params = Instruction::parse_parameters(2, op, program, index);
 
                 let value = params[0].to_owned();
                 let target = params[1].to_owned();
 
                 Instruction::JumpIfFalse { value, target }
             }
-            "07" => {
-                let params = Instruction::parse_parameters(3, &op_parts[..], program, index);
+            7 => {
+                let params = Instruction::parse_parameters(3, op, program, index);
 
                 let first = params[0].to_owned();
                 let second = params[1].to_owned();
@@ -146,8 +242,8 @@ impl Instruction {
 
                 Instruction::LessThan { first, second, out }
             }
-            "08" => {
-                let params = Instruction::parse_parameters(3, &op_parts[..], program, index);
+            8 => {
+                let params = Instruction::parse_parameters(3, op, program, index);
 
                 let first = params[0].to_owned();
                 let second = params[1].to_owned();
@@ -155,11 +251,11 @@ impl Instruction {
 
                 Instruction::Equals { first, second, out }
             }
-            "99" => {
+            99 => {
                 Instruction::Terminate
             }
 
-            _ => panic!("Unexpected instruction: {}", op_parts),
+            _ => panic!("Unexpected instruction: {}", op),
         }
     }
 
@@ -177,17 +273,16 @@ impl Instruction {
         }
     }
 
-    fn parse_parameters(number: i32, op_parts: &str, program: &Vec<i32>, instruction_pointer: &i32) -> Vec<Parameter> {
-        let mut p = Vec::new();
+    fn parse_parameters(number: i32, op: i32, program: &Vec<i32>, instruction_pointer: &i32) -> [Parameter; 3] {
+        let mut p = [Parameter::Immediate(0), Parameter::Immediate(0), Parameter::Immediate(0)];
 
-        for i in (0..3).rev().take(number as usize) {
-            let idx = i as usize;
-            let mode = &op_parts[idx..=idx];
-            let total_idx = (3 - i - 1) + *instruction_pointer + 1;
+        let mut modes = op / 100;
+        for i in 0..number {
+            let total_idx = *instruction_pointer + i + 1;
             let value = program[total_idx as usize];
 
-            let param = Parameter::parse(mode, &value, &total_idx);
-            p.push(param)
+            p[i as usize] = Parameter::parse(modes % 10, &value, &total_idx);
+            modes /= 10;
         }
 
         p
@@ -266,6 +361,7 @@ struct ExecutionResult {
     outputs: Vec<i32>,
 }
 
+#[derive(Debug)]
 struct Inputs {
     values: VecDeque<i32>
 }
@@ -337,120 +433,89 @@ struct PhaseResult {
     signal: i32,
 }
 
+// How the puzzle wires the amplifiers. In part one each amplifier runs once and passes its
+// output to the next, in part two the last one feeds back into the first.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Mode {
+    Serial,
+    Feedback,
+}
+
+impl Mode {
+    fn topology(self, amplifiers: usize) -> Topology {
+        match self {
+            Mode::Serial => Topology::serial(amplifiers),
+            Mode::Feedback => Topology::feedback(amplifiers),
+        }
+    }
+
+    // The phase settings the puzzle gives for the mode
+    fn phases(self) -> Vec<usize> {
+        match self {
+            Mode::Serial => (0..=4).collect(),
+            Mode::Feedback => (5..=9).collect(),
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Amplifier {
     program: Vec<i32>,
     instruction_pointer: i32,
-    name: String,
-    initial: bool
+    inputs: Inputs,
 }
 
 enum AmplifierResult {
     Output(i32),
+    // Needs an input nothing has sent yet
+    Waiting,
     Halt,
 }
 
 impl Amplifier {
-    fn new(program: Vec<i32>, name: char) -> Amplifier {
+    fn new(program: Vec<i32>, phase: i32) -> Amplifier {
         Amplifier {
             program,
             instruction_pointer: 0,
-            name: name.to_string(),
-            initial: true
+            inputs: phase.into(),
         }
     }
 
-    fn run(&mut self, phase: i32, input: i32) -> AmplifierResult {
+    fn push_input(&mut self, input: i32) {
+        self.inputs.push(input)
+    }
+
+    // Runs until the next output, or until the amplifier halts or has to wait for input
+    fn run(&mut self) -> AmplifierResult {
         let end = self.program.len() as i32;
         let mut outputs = Vec::new();
 
         while self.instruction_pointer < end {
-            let op = self.program[self.instruction_pointer as usize];
             let instruction = Instruction::parse(&self.instruction_pointer, &self.program);
-
-            let mut inputs = if self.initial {
-                (phase, input).into()
-            } else {
-                input.into()
-            };
-            self.initial = false;
-
-            let result = instruction.execute(&mut self.program, &mut inputs, &mut outputs);
-            match instruction {
-                Instruction::Output {..} => {
-                    match result {
-                        InstructionResult::Continue(by) => self.instruction_pointer += by,
-                        _ => panic!("Output is supposed to have a continue result")
-                    }
-
-                    let o = *outputs.last().unwrap();
-                    return AmplifierResult::Output(o)
-                }
-                _ =>  {
-                    match result {
-                        InstructionResult::Halt => return AmplifierResult::Halt,
-                        InstructionResult::Continue(by) => self.instruction_pointer += by,
-                        InstructionResult::GoTo(target) => self.instruction_pointer = target,
-                    }
+            if let Instruction::Input { .. } = instruction {
+                if self.inputs.values.is_empty() {
+                    return AmplifierResult::Waiting;
                 }
             }
 
+            match instruction.execute(&mut self.program, &mut self.inputs, &mut outputs) {
+                InstructionResult::Halt => return AmplifierResult::Halt,
+                InstructionResult::Continue(by) => self.instruction_pointer += by,
+                InstructionResult::GoTo(target) => self.instruction_pointer = target,
+            }
 
+            if let Some(output) = outputs.pop() {
+                return AmplifierResult::Output(output);
+            }
         }
 
-        unreachable!()
-    }
-}
-
-fn run_for_phase_signal(sequence: &Vec<usize>, program: Vec<i32>) -> i32 {
-    let mut amplifiers: Vec<Amplifier> = (b'A'..=b'E').map(char::from).map(|name| Amplifier::new(program.clone(), name)).collect();
-
-//        println!("amps: {:#?}", amplifiers);
-    let mut last_e_output = 0;
-    let mut next_input = 0;
-    for (index, phase) in sequence.iter().enumerate().cycle() {
-        let amp = &mut amplifiers[index];
-//        println!("Running amp: {} with input {} and phase {}", amp.name, next_input, phase);
-        let result = amp.run(*phase as i32, next_input);
-        match result {
-            AmplifierResult::Output(o) => {
-//                println!("Got output: {} from {}", o, amp.name);
-                if amp.name == "E" {
-                    last_e_output = o;
-                }
-                next_input = o;
-            },
-            AmplifierResult::Halt => {
-                println!("Got halt instruction, last from e: {}", last_e_output);
-                return last_e_output
-            },
-        }
+        AmplifierResult::Halt
     }
-
-//    for i in sequence {
-//        let mut inputs: Inputs = (*i).into();
-//        inputs.push(next_input);
-//        let result = run_intcode(program.clone(), inputs);
-//        next_input = *result.outputs.last().unwrap();
-//
-//    }
-
-    next_input
 }
 
-fn find_max_phase_signal(program: Vec<i32>, sequence: Vec<usize>) -> PhaseResult  {
-    let amplifier_count = 5;
-    let max_sequence = sequence.into_iter().permutations(amplifier_count).max_by_key(|sequence| {
-        run_for_phase_signal(sequence, program.clone())
-    }).unwrap();
-
-    let signal = run_for_phase_signal(&max_sequence, program.clone());
-
-    PhaseResult {
-        signal,
-        sequence: max_sequence.into_iter().map(|v| v as i32).collect()
-    }
-
+fn run_for_phase_signal(topology: &Topology, sequence: &[usize], program: &[i32]) -> i32 {
+    let phases: Vec<i32> = sequence.iter().map(|&phase| phase as i32).collect();
+    topology.run(program, &phases).unwrap_or(0)
 }
 
 #[cfg(test)]
@@ -460,30 +525,35 @@ mod tests {
     mod max_phase_signal {
         use super::*;
 
-//        #[test]
-//        fn example1() {
-//            let result = find_max_phase_signal(vec![3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0], (0..5).collect());
-//            assert_eq!(result.signal, 43210);
-//            assert_eq!(result.sequence, vec![4,3,2,1,0])
-//        }
-//
-//        #[test]
-//        fn example2() {
-//            let result = find_max_phase_signal(vec![3,23,3,24,1002,24,10,24,1002,23,-1,23,101,5,23,23,1,24,23,23,4,23,99,0,0], (0..5).collect());
-//            assert_eq!(result.signal, 54321);
-//            assert_eq!(result.sequence, vec![0,1,2,3,4])
-//        }
-//
-//        #[test]
-//        fn example3() {
-//            let result = find_max_phase_signal(vec![3,31,3,32,1002,32,10,32,1001,31,-2,31,1007,31,0,33,1002,33,7,33,1,33,31,31,1,32,31,31,4,31,99,0,0,0], (0..5).collect());
-//            assert_eq!(result.signal, 65210);
-//            assert_eq!(result.sequence, vec![1,0,4,3,2])
-//        }
+        fn find_max_phase_signal(program: Vec<i32>, sequence: Vec<usize>, mode: Mode) -> PhaseResult {
+            let topology = mode.topology(sequence.len());
+            find_max_signal(&program, &sequence, &topology)
+        }
+
+        #[test]
+        fn example1() {
+            let result = find_max_phase_signal(vec![3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0], (0..5).collect(), Mode::Serial);
+            assert_eq!(result.signal, 43210);
+            assert_eq!(result.sequence, vec![4,3,2,1,0])
+        }
+
+        #[test]
+        fn example2() {
+            let result = find_max_phase_signal(vec![3,23,3,24,1002,24,10,24,1002,23,-1,23,101,5,23,23,1,24,23,23,4,23,99,0,0], (0..5).collect(), Mode::Serial);
+            assert_eq!(result.signal, 54321);
+            assert_eq!(result.sequence, vec![0,1,2,3,4])
+        }
+
+        #[test]
+        fn example3() {
+            let result = find_max_phase_signal(vec![3,31,3,32,1002,32,10,32,1001,31,-2,31,1007,31,0,33,1002,33,7,33,1,33,31,31,1,32,31,31,4,31,99,0,0,0], (0..5).collect(), Mode::Serial);
+            assert_eq!(result.signal, 65210);
+            assert_eq!(result.sequence, vec![1,0,4,3,2])
+        }
 
         #[test]
         fn example4() {
-            let result = find_max_phase_signal(vec![3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5], (5..=9).collect());
+            let result = find_max_phase_signal(vec![3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5], (5..=9).collect(), Mode::Feedback);
             assert_eq!(result.signal, 139629729);
             assert_eq!(result.sequence, vec![9,8,7,6,5]);
         }
@@ -492,7 +562,7 @@ mod tests {
         fn example5() {
             let result = find_max_phase_signal(vec![3,52,1001,52,-5,52,3,53,1,52,56,54,1007,54,5,55,1005,55,26,1001,54,
                                                     -5,54,1105,1,12,1,53,54,53,1008,54,0,55,1001,55,1,55,2,53,55,53,4,
-                                                    53,1001,56,-1,56,1005,56,6,99,0,0,0,0,10], (5..=9).collect());
+                                                    53,1001,56,-1,56,1005,56,6,99,0,0,0,0,10], (5..=9).collect(), Mode::Feedback);
             assert_eq!(result.signal, 18216);
             assert_eq!(result.sequence, vec![9,7,8,5,6]);
         }
@@ -501,6 +571,8 @@ mod tests {
 
     mod intcode_machine {
         use super::*;
+        use intcode::fuzz::{self, Generator, Rng};
+        use intcode::Limits;
         #[test]
         fn example1() {
             let result = run_intcode(vec![1, 0, 0, 0, 99], 1.into());
@@ -658,11 +730,31 @@ mod tests {
 
         #[test]
         fn gets_parameters() {
-            let opcode = "01105";
+            let opcode = 1105;
 
             let program = vec![1105, 2, 4];
             let params = Instruction::parse_parameters(2, opcode, &program, &0);
-            assert_eq!(params, vec![Parameter::parse("1", &2, &1), Parameter::parse("1", &4, &2)]);
+            assert_eq!(params[..2], [Parameter::parse(1, &2, &1), Parameter::parse(1, &4, &2)]);
+        }
+
+        #[test]
+        fn matches_the_shared_machine() {
+            let generator = Generator::new(&intcode::OPCODES);
+            for seed in 0..500 {
+                let mut rng = Rng::new(seed);
+                let program = generator.generate(&mut rng);
+                let values: Vec<i64> = (0..program.len()).map(|_| rng.range(-100..=100)).collect();
+
+                let run = fuzz::run(&program, &values, Limits::default());
+                if run.largest > i32::MAX as u64 {
+                    continue;
+                }
+                let mut inputs = Inputs::new();
+                values.iter().for_each(|&value| inputs.push(value as i32));
+                let result = run_intcode(program.iter().map(|&cell| cell as i32).collect(), inputs);
+                assert_eq!(result.program.iter().map(|&cell| cell as i64).collect::<Vec<_>>(), run.memory, "seed {}", seed);
+                assert_eq!(result.outputs.iter().map(|&cell| cell as i64).collect::<Vec<_>>(), run.outputs, "seed {}", seed);
+            }
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,238 @@
+// Finds the phases giving the highest signal, trying them on every core. A serial chain is
+// searched one amplifier at a time instead, so every prefix of phases is only run once however
+// many sequences start with it. With the sequences already spread over the threads, the
+// amplifiers in each run take turns on the thread it's on.
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use itertools::Itertools;
+
+use super::topology::Topology;
+use super::{run_for_phase_signal, Amplifier, AmplifierResult, PhaseResult};
+
+// Every amplifier gets a different one of `phases`. Ties go to the sequence that comes first,
+// in the order `permutations` gives them. Repeated phases give the same sequences more than
+// once, which are only ranked the first time.
+pub fn find_max_signal(program: &[i32], phases: &[usize], topology: &Topology) -> PhaseResult {
+    let top = find_top_signals(program, phases, topology, 1);
+    top.into_iter().next().expect("There are fewer phases than amplifiers")
+}
+
+// The `count` sequences with the highest signals, best first
+pub fn find_top_signals(program: &[i32], phases: &[usize], topology: &Topology, count: usize) -> Vec<PhaseResult> {
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let top = if topology.is_serial() {
+        top_serial(program, phases, topology.len(), threads, count)
+    } else {
+        top_of_all(program, phases, topology, threads, count)
+    };
+
+    top.into_iter()
+        .map(|(signal, sequence)| PhaseResult {
+            signal,
+            sequence: sequence.into_iter().map(|phase| phase as i32).collect(),
+        })
+        .collect()
+}
+
+// The highest signals seen so far, earlier entries first among equal signals
+struct Top {
+    count: usize,
+    entries: Vec<(i32, Vec<usize>)>,
+}
+
+impl Top {
+    fn new(count: usize) -> Top {
+        Top { count, entries: Vec::new() }
+    }
+
+    fn offer(&mut self, signal: i32, sequence: &[usize]) {
+        let position = self.entries.partition_point(|(best, _)| *best >= signal);
+        if position < self.count {
+            self.entries.insert(position, (signal, sequence.to_vec()));
+            self.entries.truncate(self.count);
+        }
+    }
+}
+
+// Runs `work` for the indices 0..jobs spread over the threads, and merges the rankings they
+// return. Among equal signals the ones from lower indices come first.
+fn top_of_jobs<F>(jobs: usize, threads: usize, count: usize, work: F) -> Vec<(i32, Vec<usize>)>
+where
+    F: Fn(usize) -> Top + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, usize, i32, Vec<usize>)> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        if index >= jobs {
+                            return results;
+                        }
+                        let top = work(index).entries.into_iter().enumerate();
+                        results.extend(top.map(|(rank, (signal, sequence))| (index, rank, signal, sequence)));
+                    }
+                })
+            })
+            .collect();
+        handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+    });
+
+    results.sort_by(|a, b| b.2.cmp(&a.2).then((a.0, a.1).cmp(&(b.0, b.1))));
+    results.into_iter().take(count).map(|(_, _, signal, sequence)| (signal, sequence)).collect()
+}
+
+fn top_of_all(program: &[i32], phases: &[usize], topology: &Topology, threads: usize, count: usize) -> Vec<(i32, Vec<usize>)> {
+    let sequences: Vec<Vec<usize>> = phases.iter().copied().permutations(topology.len()).unique().collect();
+    top_of_jobs(sequences.len(), threads, count, |index| {
+        let mut top = Top::new(1);
+        top.offer(run_for_phase_signal(topology, &sequences[index], program), &sequences[index]);
+        top
+    })
+}
+
+// A job for every choice of the first phase, each searching the rest depth first
+fn top_serial(program: &[i32], phases: &[usize], amplifiers: usize, threads: usize, count: usize) -> Vec<(i32, Vec<usize>)> {
+    if amplifiers > phases.len() {
+        return Vec::new();
+    }
+
+    top_of_jobs(phases.len(), threads, count, |first| {
+        if phases[..first].contains(&phases[first]) {
+            return Top::new(count);
+        }
+        let mut chain = Chain { program, phases, amplifiers, outputs: HashMap::new(), top: Top::new(count) };
+        let mut used = vec![false; phases.len()];
+        used[first] = true;
+        let outputs = chain.amplify(phases[first], &[0]);
+        chain.search(&mut vec![phases[first]], &mut used, &outputs);
+        chain.top
+    })
+}
+
+struct Chain<'a> {
+    program: &'a [i32],
+    phases: &'a [usize],
+    amplifiers: usize,
+    // What an amplifier outputs for a phase and everything it's sent. All of them run the same
+    // program, so this is shared between the positions in the chain.
+    outputs: HashMap<(usize, Vec<i32>), Vec<i32>>,
+    top: Top,
+}
+
+impl Chain<'_> {
+    // In a chain an amplifier is sent everything the one before it outputs, so what it outputs
+    // only depends on its phase and those values
+    fn amplify(&mut self, phase: usize, inputs: &[i32]) -> Vec<i32> {
+        let program = self.program;
+        self.outputs
+            .entry((phase, inputs.to_vec()))
+            .or_insert_with(|| {
+                let mut amplifier = Amplifier::new(program.to_vec(), phase as i32);
+                inputs.iter().for_each(|&input| amplifier.push_input(input));
+
+                let mut outputs = Vec::new();
+                while let AmplifierResult::Output(value) = amplifier.run() {
+                    outputs.push(value);
+                }
+                outputs
+            })
+            .clone()
+    }
+
+    // `inputs` is what the amplifiers so far, with the phases in `sequence`, send to the next one
+    fn search(&mut self, sequence: &mut Vec<usize>, used: &mut Vec<bool>, inputs: &[i32]) {
+        if sequence.len() == self.amplifiers {
+            self.top.offer(inputs.last().copied().unwrap_or(0), sequence);
+            return;
+        }
+
+        let mut tried = Vec::new();
+        for index in 0..self.phases.len() {
+            let phase = self.phases[index];
+            if used[index] || tried.contains(&phase) {
+                continue;
+            }
+            tried.push(phase);
+            let outputs = self.amplify(phase, inputs);
+
+            used[index] = true;
+            sequence.push(phase);
+            self.search(sequence, used, &outputs);
+            sequence.pop();
+            used[index] = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every amplifier multiplies its input by 10 and adds its phase
+    const DIGITS: &[i32] = &[3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0];
+
+    fn brute_force(program: &[i32], phases: &[usize], topology: &Topology) -> (i32, Vec<i32>) {
+        // The first of the best, like the search
+        let mut best: Option<(i32, Vec<usize>)> = None;
+        for sequence in phases.iter().copied().permutations(topology.len()) {
+            let signal = run_for_phase_signal(topology, &sequence, program);
+            if best.as_ref().is_none_or(|(best, _)| signal > *best) {
+                best = Some((signal, sequence));
+            }
+        }
+
+        let (signal, sequence) = best.unwrap();
+        (signal, sequence.into_iter().map(|phase| phase as i32).collect())
+    }
+
+    #[test]
+    fn searches_chains_one_amplifier_at_a_time() {
+        let result = find_max_signal(DIGITS, &[0, 1, 2, 3, 4, 5, 6, 7], &Topology::serial(7));
+        assert_eq!(result.signal, 7654321);
+        assert_eq!(result.sequence, vec![7, 6, 5, 4, 3, 2, 1]);
+
+        // Choosing from repeated phases
+        let result = find_max_signal(DIGITS, &[1, 2, 1], &Topology::serial(2));
+        assert_eq!((result.signal, result.sequence), (21, vec![2, 1]));
+    }
+
+    #[test]
+    fn agrees_with_trying_every_sequence() {
+        let program = [3,23,3,24,1002,24,10,24,1002,23,-1,23,101,5,23,23,1,24,23,23,4,23,99,0,0];
+        for topology in &[Topology::serial(4), Topology::new(4).connect(0, 1).connect(1, 2).connect(2, 3).connect(0, 3)] {
+            let result = find_max_signal(&program, &[0, 1, 2, 3, 4], topology);
+            assert_eq!((result.signal, result.sequence), brute_force(&program, &[0, 1, 2, 3, 4], topology));
+        }
+    }
+
+    #[test]
+    fn ranks_the_best_sequences() {
+        let signals = |top: Vec<PhaseResult>| top.into_iter().map(|result| (result.signal, result.sequence)).collect::<Vec<_>>();
+
+        let top = find_top_signals(DIGITS, &[0, 1, 2, 3, 4], &Topology::serial(3), 3);
+        assert_eq!(signals(top), vec![(432, vec![4, 3, 2]), (431, vec![4, 3, 1]), (430, vec![4, 3, 0])]);
+
+        // The same without taking the shortcut for chains
+        let topology = Topology::serial(3).connect(0, 0);
+        let top = find_top_signals(DIGITS, &[0, 1, 2, 3, 4], &topology, 3);
+        assert_eq!(top.len(), 3);
+        assert_eq!(top[0].signal, find_max_signal(DIGITS, &[0, 1, 2, 3, 4], &topology).signal);
+        assert!(top.windows(2).all(|pair| pair[0].signal >= pair[1].signal));
+
+        assert_eq!(find_top_signals(DIGITS, &[0, 1], &Topology::serial(2), 5).len(), 2);
+    }
+
+    #[test]
+    fn ranks_repeated_phases_once() {
+        let expected = vec![(21, vec![2, 1]), (12, vec![1, 2]), (11, vec![1, 1])];
+        for topology in &[Topology::serial(2), Topology::serial(2).connect(1, 1)] {
+            let top = find_top_signals(DIGITS, &[1, 2, 1], topology, 5);
+            assert_eq!(top.into_iter().map(|result| (result.signal, result.sequence)).collect::<Vec<_>>(), expected);
+        }
+    }
+}
@@ -0,0 +1,453 @@
+// How the amplifiers are wired together. Every amplifier is given its phase setting first, then
+// the values sent to it by the amplifiers wired to it, in the order they were sent. The entry
+// amplifiers also get the 0 signal everything starts from.
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use super::{Amplifier, AmplifierResult};
+
+// Whether the amplifiers take turns on one thread, or run on a thread each
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Execution {
+    Turns,
+    Threads,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Topology {
+    // Where the outputs of each amplifier go, every output is sent to all of them
+    outputs: Vec<Vec<usize>>,
+    entries: Vec<usize>,
+    // The amplifier whose last output is the signal
+    signal: usize,
+}
+
+impl Topology {
+    // `count` amplifiers with no wires between them. The first one gets the starting signal and
+    // the signal comes out of the last one.
+    pub fn new(count: usize) -> Topology {
+        assert!(count > 0, "There has to be at least one amplifier");
+        Topology {
+            outputs: vec![Vec::new(); count],
+            entries: vec![0],
+            signal: count - 1,
+        }
+    }
+
+    // Each amplifier sends its output to the next one, like in part one
+    pub fn serial(count: usize) -> Topology {
+        (1..count).fold(Topology::new(count), |topology, to| topology.connect(to - 1, to))
+    }
+
+    // Same as serial, but the last amplifier also sends its output back to the first one, like
+    // in part two
+    pub fn feedback(count: usize) -> Topology {
+        Topology::serial(count).connect(count - 1, 0)
+    }
+
+    pub fn connect(mut self, from: usize, to: usize) -> Topology {
+        assert!(from < self.len() && to < self.len(), "No amplifier {} or {}", from, to);
+        self.outputs[from].push(to);
+        self
+    }
+
+    // The amplifiers given the starting signal, instead of only the first one
+    pub fn entries(mut self, entries: &[usize]) -> Topology {
+        assert!(entries.iter().all(|&entry| entry < self.len()), "No amplifier in {:?}", entries);
+        self.entries = entries.to_vec();
+        self
+    }
+
+    // Takes the signal from another amplifier than the last one
+    pub fn signal(mut self, amplifier: usize) -> Topology {
+        assert!(amplifier < self.len(), "No amplifier {}", amplifier);
+        self.signal = amplifier;
+        self
+    }
+
+    // Wires written like `>A>B>C>` or `A>B, A>C, B>D, C>D`, where a wire into an amplifier from
+    // nothing makes it an entry, and one out of an amplifier to nothing takes the signal from it.
+    // Without those the first amplifier is the entry and the last gives the signal, as with
+    // `new`. There are as many amplifiers as the last one named.
+    pub fn parse(description: &str) -> Result<Topology, String> {
+        let mut wires = Vec::new();
+        let mut entries = Vec::new();
+        let mut signals = Vec::new();
+        let mut last = None;
+        for chain in description.split(|c: char| c == ',' || c.is_whitespace()).filter(|chain| !chain.is_empty()) {
+            let names: Vec<&str> = chain.split('>').collect();
+            if names.len() < 2 {
+                return Err(format!("{} has no wires", chain));
+            }
+            let mut amplifiers = Vec::new();
+            for (position, name) in names.iter().enumerate() {
+                match amplifier_index(name) {
+                    Some(amplifier) => {
+                        amplifiers.push(amplifier);
+                        last = last.max(Some(amplifier));
+                    }
+                    None if name.is_empty() && position == 0 => {}
+                    None if name.is_empty() && position == names.len() - 1 => {}
+                    None => return Err(format!("{:?} isn't an amplifier in {}", name, chain)),
+                }
+            }
+            if names[0].is_empty() {
+                entries.extend(amplifiers.first());
+            }
+            if names[names.len() - 1].is_empty() {
+                signals.extend(amplifiers.last());
+            }
+            wires.extend(amplifiers.windows(2).map(|pair| (pair[0], pair[1])));
+        }
+
+        let count = last.ok_or_else(|| "There has to be at least one amplifier".to_string())? + 1;
+        let mut topology = wires.into_iter().fold(Topology::new(count), |topology, (from, to)| topology.connect(from, to));
+        if !entries.is_empty() {
+            topology = topology.entries(&entries);
+        }
+        match signals.as_slice() {
+            [] => Ok(topology),
+            [amplifier] => Ok(topology.signal(*amplifier)),
+            _ => Err("Only one amplifier can give the signal".to_string()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.outputs.len()
+    }
+
+    // Whether it's a plain chain like `serial` gives, where every amplifier only depends on the
+    // ones before it
+    pub fn is_serial(&self) -> bool {
+        *self == Topology::serial(self.len())
+    }
+
+    pub fn run_with(&self, execution: Execution, program: &[i32], phases: &[i32]) -> Option<i32> {
+        match execution {
+            Execution::Turns => self.run(program, phases),
+            Execution::Threads => self.run_threaded(program, phases),
+        }
+    }
+
+    // Runs a copy of the program for every amplifier, one phase each, until all of them have
+    // halted or are waiting for a value that nothing is going to send. Returns the last output
+    // of the signal amplifier, if it had any.
+    pub fn run(&self, program: &[i32], phases: &[i32]) -> Option<i32> {
+        self.run_observed(program, phases, |_| {})
+    }
+
+    // Same as `run`, keeping every value passed along the way
+    pub fn trace(&self, program: &[i32], phases: &[i32]) -> Trace {
+        let mut transfers = Vec::new();
+        self.run_observed(program, phases, |transfer| transfers.push(transfer));
+        Trace { transfers }
+    }
+
+    fn run_observed<F: FnMut(Transfer)>(&self, program: &[i32], phases: &[i32], mut on_transfer: F) -> Option<i32> {
+        assert_eq!(phases.len(), self.len(), "Every amplifier needs a phase");
+
+        let mut amplifiers: Vec<Amplifier> = phases.iter().map(|&phase| Amplifier::new(program.to_vec(), phase)).collect();
+        for &entry in &self.entries {
+            on_transfer(Transfer { iteration: 0, from: None, to: Some(entry), value: 0 });
+            amplifiers[entry].push_input(0);
+        }
+
+        let mut halted = vec![false; self.len()];
+        // How many values each amplifier has output, which is the iteration it's on
+        let mut iterations = vec![0; self.len()];
+        let mut signal = None;
+        loop {
+            let mut progress = false;
+
+            for index in 0..self.len() {
+                while !halted[index] {
+                    match amplifiers[index].run() {
+                        AmplifierResult::Output(value) => {
+                            progress = true;
+                            let iteration = iterations[index];
+                            iterations[index] += 1;
+
+                            if index == self.signal {
+                                on_transfer(Transfer { iteration, from: Some(index), to: None, value });
+                                signal = Some(value);
+                            }
+                            for &to in &self.outputs[index] {
+                                on_transfer(Transfer { iteration, from: Some(index), to: Some(to), value });
+                                amplifiers[to].push_input(value);
+                            }
+                        }
+                        AmplifierResult::Waiting => break,
+                        AmplifierResult::Halt => {
+                            progress = true;
+                            halted[index] = true;
+                        }
+                    }
+                }
+            }
+
+            if !progress {
+                return signal;
+            }
+        }
+    }
+
+    // Same as `run`, but every amplifier runs on its own thread and values are sent between
+    // them over channels. The amplifiers tell this thread what they're doing, so it can stop them
+    // once the signal amplifier halts or all of them are waiting with nothing on its way.
+    pub fn run_threaded(&self, program: &[i32], phases: &[i32]) -> Option<i32> {
+        assert_eq!(phases.len(), self.len(), "Every amplifier needs a phase");
+
+        let (senders, receivers): (Vec<Sender<Message>>, Vec<Receiver<Message>>) = (0..self.len()).map(|_| mpsc::channel()).unzip();
+        let mut sent = vec![0; self.len()];
+        for &entry in &self.entries {
+            senders[entry].send(Message::Value(0)).unwrap();
+            sent[entry] += 1;
+        }
+
+        let (events, from_amplifiers) = mpsc::channel();
+        thread::scope(|scope| {
+            for (index, inputs) in receivers.into_iter().enumerate() {
+                let amplifier = Amplifier::new(program.to_vec(), phases[index]);
+                let targets: Vec<(usize, Sender<Message>)> = self.outputs[index].iter().map(|&to| (to, senders[to].clone())).collect();
+                let is_signal = index == self.signal;
+                let events = events.clone();
+                scope.spawn(move || run_amplifier(index, amplifier, inputs, targets, is_signal, events));
+            }
+            drop(events);
+
+            let mut waiting = vec![None; self.len()];
+            let mut halted = vec![false; self.len()];
+            let mut signal = None;
+            while let Ok(event) = from_amplifiers.recv() {
+                match event {
+                    Event::Sent { to } => sent[to] += 1,
+                    Event::Signal(value) => signal = Some(value),
+                    Event::Waiting { amplifier, received } => waiting[amplifier] = Some(received),
+                    Event::Halted { amplifier } => halted[amplifier] = true,
+                }
+
+                let idle = (0..self.len()).all(|amplifier| halted[amplifier] || waiting[amplifier] == Some(sent[amplifier]));
+                if halted[self.signal] || idle {
+                    break;
+                }
+            }
+
+            for sender in &senders {
+                let _ = sender.send(Message::Stop);
+            }
+            signal
+        })
+    }
+}
+
+// A value passed to an amplifier. The iteration counts the values the sender has output
+// before this one, so in a feedback loop it's the number of times around the loop.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Transfer {
+    pub iteration: usize,
+    // None for the starting signal
+    pub from: Option<usize>,
+    // None for the signal itself
+    pub to: Option<usize>,
+    pub value: i32,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Trace {
+    pub transfers: Vec<Transfer>,
+}
+
+impl Trace {
+    pub fn signal(&self) -> Option<i32> {
+        self.transfers.iter().rev().find(|transfer| transfer.to.is_none()).map(|transfer| transfer.value)
+    }
+
+    // One row per value, with the amplifiers named A, B, C and so on like in the puzzle
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("iteration,from,to,value\n");
+        for transfer in &self.transfers {
+            let from = transfer.from.map_or_else(|| "start".to_string(), amplifier_name);
+            let to = transfer.to.map_or_else(|| "signal".to_string(), amplifier_name);
+            csv.push_str(&format!("{},{},{},{}\n", transfer.iteration, from, to, transfer.value));
+        }
+        csv
+    }
+}
+
+fn amplifier_name(index: usize) -> String {
+    if index < 26 {
+        char::from(b'A' + index as u8).to_string()
+    } else {
+        index.to_string()
+    }
+}
+
+fn amplifier_index(name: &str) -> Option<usize> {
+    match name.as_bytes() {
+        [letter @ b'A'..=b'Z'] => Some((letter - b'A') as usize),
+        _ => name.parse().ok(),
+    }
+}
+
+enum Message {
+    Value(i32),
+    Stop,
+}
+
+// What an amplifier thread tells the thread running the topology
+enum Event {
+    // Sent before the value itself, so it's always counted before it can be received
+    Sent { to: usize },
+    Signal(i32),
+    // Waiting for a value after having received `received` of them
+    Waiting { amplifier: usize, received: usize },
+    Halted { amplifier: usize },
+}
+
+fn run_amplifier(index: usize, mut amplifier: Amplifier, inputs: Receiver<Message>, targets: Vec<(usize, Sender<Message>)>, is_signal: bool, events: Sender<Event>) {
+    let mut received = 0;
+    loop {
+        match amplifier.run() {
+            AmplifierResult::Output(value) => {
+                if is_signal {
+                    let _ = events.send(Event::Signal(value));
+                }
+                for (to, target) in &targets {
+                    let _ = events.send(Event::Sent { to: *to });
+                    // Fails if the amplifier has already halted, and doesn't need it anymore
+                    let _ = target.send(Message::Value(value));
+                }
+            }
+            AmplifierResult::Waiting => {
+                let _ = events.send(Event::Waiting { amplifier: index, received });
+                match inputs.recv() {
+                    Ok(Message::Value(value)) => {
+                        amplifier.push_input(value);
+                        received += 1;
+                    }
+                    Ok(Message::Stop) | Err(_) => return,
+                }
+            }
+            AmplifierResult::Halt => {
+                let _ = events.send(Event::Halted { amplifier: index });
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Itertools;
+
+    // Takes as many inputs as its phase, and outputs one more than their sum
+    fn summing_program() -> Vec<i32> {
+        let program = intcode::asm::assemble(
+            "in count
+            loop: jf count, #done
+            in x
+            add sum, x, sum
+            add count, #-1, count
+            jt #1, #loop
+            done: add sum, #1, sum
+            out sum
+            hlt
+            count: data 0
+            x: data 0
+            sum: data 0",
+        )
+        .unwrap();
+        program.into_iter().map(|cell| cell as i32).collect()
+    }
+
+    const SERIAL_EXAMPLES: [&[i32]; 3] = [
+        &[3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0],
+        &[3,23,3,24,1002,24,10,24,1002,23,-1,23,101,5,23,23,1,24,23,23,4,23,99,0,0],
+        &[3,31,3,32,1002,32,10,32,1001,31,-2,31,1007,31,0,33,1002,33,7,33,1,33,31,31,1,32,31,31,4,31,99,0,0,0],
+    ];
+
+    const FEEDBACK_EXAMPLES: [&[i32]; 2] = [
+        &[3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5],
+        &[3,52,1001,52,-5,52,3,53,1,52,56,54,1007,54,5,55,1005,55,26,1001,54,-5,54,1105,1,12,1,53,54,53,1008,54,0,55,1001,55,1,55,2,53,55,53,4,53,1001,56,-1,56,1005,56,6,99,0,0,0,0,10],
+    ];
+
+    #[test]
+    fn runs_the_presets() {
+        let program = vec![3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0];
+        assert_eq!(Topology::serial(5).run(&program, &[4, 3, 2, 1, 0]), Some(43210));
+
+        let program = vec![3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5];
+        assert_eq!(Topology::feedback(5).run(&program, &[9, 8, 7, 6, 5]), Some(139629729));
+    }
+
+    #[test]
+    fn fans_out_and_in() {
+        // 0 sends to both 1 and 2, which both send to 3
+        let diamond = Topology::new(4).connect(0, 1).connect(0, 2).connect(1, 3).connect(2, 3);
+        assert_eq!(diamond.run(&summing_program(), &[1, 1, 1, 2]), Some(5));
+        assert_eq!(diamond.signal(1).run(&summing_program(), &[1, 1, 1, 2]), Some(2));
+
+        // Two chains started separately, joined at the end
+        let joined = Topology::new(3).entries(&[0, 1]).connect(0, 2).connect(1, 2);
+        assert_eq!(joined.run(&summing_program(), &[1, 1, 2]), Some(3));
+    }
+
+    #[test]
+    fn reads_descriptions() {
+        assert_eq!(Topology::parse(">A>B>C>D>E>"), Ok(Topology::serial(5)));
+        assert_eq!(Topology::parse("A>B>C>D>E>A"), Ok(Topology::feedback(5)));
+        assert_eq!(Topology::parse("A>B, A>C B>D,C>D"), Ok(Topology::new(4).connect(0, 1).connect(0, 2).connect(1, 3).connect(2, 3)));
+        assert_eq!(Topology::parse(">A>C,>B>C"), Ok(Topology::new(3).entries(&[0, 1]).connect(0, 2).connect(1, 2)));
+        assert_eq!(Topology::parse("A>B>D B>C>"), Ok(Topology::new(4).connect(0, 1).connect(1, 3).connect(1, 2).signal(2)));
+        assert_eq!(Topology::parse(">27>"), Ok(Topology::new(28).entries(&[27]).signal(27)));
+
+        assert!(Topology::parse("").is_err());
+        assert!(Topology::parse("A").is_err());
+        assert!(Topology::parse("A>>B").is_err());
+        assert!(Topology::parse("A>b").is_err());
+        assert!(Topology::parse("A>B>,B>C>").is_err());
+    }
+
+    #[test]
+    fn traces_every_value() {
+        let program = SERIAL_EXAMPLES[0];
+        let trace = Topology::serial(3).trace(program, &[1, 2, 3]);
+        assert_eq!(trace.signal(), Some(123));
+        assert_eq!(trace.to_csv(), "iteration,from,to,value\n0,start,A,0\n0,A,B,1\n0,B,C,12\n0,C,signal,123\n");
+
+        let trace = Topology::feedback(5).trace(FEEDBACK_EXAMPLES[0], &[9, 8, 7, 6, 5]);
+        assert_eq!(trace.signal(), Some(139629729));
+        // The starting signal, then five times around the loop where E also outputs the signal
+        assert_eq!(trace.transfers.len(), 1 + 5 * 6);
+        let last = trace.transfers.iter().filter(|transfer| transfer.iteration == 4).count();
+        assert_eq!(last, 6);
+        let wiring: Vec<(usize, Option<usize>, Option<usize>)> = trace.transfers[..8].iter().map(|t| (t.iteration, t.from, t.to)).collect();
+        assert_eq!(
+            wiring,
+            vec![(0, None, Some(0)), (0, Some(0), Some(1)), (0, Some(1), Some(2)), (0, Some(2), Some(3)), (0, Some(3), Some(4)), (0, Some(4), None), (0, Some(4), Some(0)), (1, Some(0), Some(1))]
+        );
+    }
+
+    #[test]
+    fn stops_when_nothing_can_continue() {
+        // Nothing is wired to the signal amplifier, so it waits forever
+        assert_eq!(Topology::new(2).run(&summing_program(), &[1, 1]), None);
+        assert_eq!(Topology::new(2).run_threaded(&summing_program(), &[1, 1]), None);
+    }
+
+    #[test]
+    fn threads_agree_with_taking_turns() {
+        let examples = SERIAL_EXAMPLES.iter().map(|program| (program, Topology::serial(5), 0))
+            .chain(FEEDBACK_EXAMPLES.iter().map(|program| (program, Topology::feedback(5), 5)));
+
+        for (program, topology, first_phase) in examples {
+            for phases in (first_phase..first_phase + 5).permutations(5) {
+                assert_eq!(topology.run_threaded(program, &phases), topology.run(program, &phases), "{:?}", phases);
+            }
+        }
+
+        let diamond = Topology::new(4).connect(0, 1).connect(0, 2).connect(1, 3).connect(2, 3);
+        assert_eq!(diamond.run_threaded(&summing_program(), &[1, 1, 1, 2]), Some(5));
+    }
+}
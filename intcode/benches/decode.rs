@@ -0,0 +1,84 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use intcode::{asm, Instruction, Machine};
+
+// The countdown below, compiled with `intcode compile`
+#[path = "../tests/compiled/countdown.rs"]
+mod countdown;
+
+// How the day5 and day7 solutions used to decode, by formatting every opcode as a string
+fn decode_with_strings(word: i64) -> (i64, [i64; 3]) {
+    let parts = format!("{:05}", word);
+    let opcode = parts[3..5].parse().unwrap();
+
+    let mut modes = [0; 3];
+    for (mode, index) in modes.iter_mut().zip((0..3).rev()) {
+        *mode = parts[index..=index].parse().unwrap();
+    }
+
+    (opcode, modes)
+}
+
+fn instruction_words() -> Vec<i64> {
+    vec![3, 1008, 1005, 107, 1006, 1106, 1002, 4, 1105, 104, 1101, 1001, 1, 1007, 2, 99]
+}
+
+fn countdown() -> Vec<i64> {
+    asm::assemble(
+        "
+                in   n
+        loop:   add  n, #-1, n
+                jt   n, #loop
+                out  n
+                hlt
+        n:      data 0
+        ",
+    )
+    .unwrap()
+}
+
+fn decoding(c: &mut Criterion) {
+    let words = instruction_words();
+
+    c.bench_function("decode with strings", |b| {
+        b.iter(|| {
+            for word in &words {
+                black_box(decode_with_strings(black_box(*word)));
+            }
+        })
+    });
+
+    c.bench_function("decode with integers", |b| {
+        b.iter(|| {
+            for word in &words {
+                black_box(Instruction::decode(black_box(*word)));
+            }
+        })
+    });
+}
+
+fn running(c: &mut Criterion) {
+    let program = countdown();
+
+    c.bench_function("run 20000 instructions", |b| {
+        b.iter(|| {
+            let mut machine = Machine::new(program.clone());
+            machine.push_input(10_000);
+            machine.run().unwrap();
+            black_box(machine.take_outputs())
+        })
+    });
+
+    assert_eq!(countdown::PROGRAM, &program[..]);
+    c.bench_function("run 20000 compiled instructions", |b| {
+        b.iter(|| {
+            let mut machine = Machine::new(program.clone());
+            machine.push_input(10_000);
+            machine.run_compiled(countdown::run).unwrap();
+            black_box(machine.take_outputs())
+        })
+    });
+}
+
+criterion_group!(benches, decoding, running);
+criterion_main!(benches);
@@ -0,0 +1,296 @@
+// Assembler for a small Intcode assembly language, so test programs don't have to be
+// hand encoded.
+//
+//     ; comments run to the end of the line
+//     size = 3                ; constants
+//     loop:   in  x           ; labels evaluate to the address of what follows them
+//             mul x, #size, x ; bare operands are position mode, `#` is immediate
+//             out @0          ; `@` is relative mode
+//             jt  #1, #loop
+//     x:      data 0          ; raw cells
+//
+// Operands are sums and differences of numbers, labels and constants, e.g. `#x+1`.
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::{Instruction, Mode, Opcode};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Error {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Clone, Debug)]
+enum Term {
+    Number(i64),
+    Symbol(String),
+}
+
+#[derive(Clone, Debug)]
+struct Expr {
+    terms: Vec<(i64, Term)>,
+}
+
+enum Symbol {
+    Label(i64),
+    Constant(Expr),
+}
+
+enum Statement {
+    Instruction { instruction: Instruction, operands: Vec<Expr> },
+    Data(Vec<Expr>),
+}
+
+pub fn assemble(source: &str) -> Result<Vec<i64>, Error> {
+    let mut symbols = HashMap::new();
+    let mut statements = Vec::new();
+    let mut address = 0;
+
+    for (index, raw) in source.lines().enumerate() {
+        let line = index + 1;
+        let error = |message: String| Error { line, message };
+
+        let mut text = raw.split(';').next().unwrap().trim();
+
+        while let Some(colon) = text.find(':') {
+            let label = text[..colon].trim();
+            define(&mut symbols, label, Symbol::Label(address as i64)).map_err(error)?;
+            text = text[colon + 1..].trim();
+        }
+
+        if text.is_empty() {
+            continue;
+        }
+
+        if let Some(equals) = text.find('=') {
+            let name = text[..equals].trim();
+            let value = parse_expr(&text[equals + 1..]).map_err(error)?;
+            define(&mut symbols, name, Symbol::Constant(value)).map_err(error)?;
+            continue;
+        }
+
+        let statement = parse_statement(text).map_err(error)?;
+        address += match &statement {
+            Statement::Instruction { instruction, .. } => instruction.length(),
+            Statement::Data(values) => values.len(),
+        };
+        statements.push((line, statement));
+    }
+
+    let mut program = Vec::with_capacity(address);
+    for (line, statement) in statements {
+        let error = |message: String| Error { line, message };
+        match statement {
+            Statement::Instruction { instruction, operands } => {
+                program.push(instruction.encode());
+                for operand in operands {
+                    program.push(evaluate(&operand, &symbols, 0).map_err(error)?);
+                }
+            }
+            Statement::Data(values) => {
+                for value in values {
+                    program.push(evaluate(&value, &symbols, 0).map_err(error)?);
+                }
+            }
+        }
+    }
+
+    Ok(program)
+}
+
+fn define(symbols: &mut HashMap<String, Symbol>, name: &str, symbol: Symbol) -> Result<(), String> {
+    if !is_identifier(name) {
+        return Err(format!("invalid symbol name `{}`", name));
+    }
+
+    if symbols.insert(name.to_string(), symbol).is_some() {
+        return Err(format!("`{}` is defined more than once", name));
+    }
+
+    Ok(())
+}
+
+fn parse_statement(text: &str) -> Result<Statement, String> {
+    let (mnemonic, rest) = match text.find(char::is_whitespace) {
+        Some(split) => (&text[..split], text[split..].trim()),
+        None => (text, ""),
+    };
+
+    let operands: Vec<&str> = if rest.is_empty() {
+        Vec::new()
+    } else {
+        rest.split(',').map(str::trim).collect()
+    };
+
+    if mnemonic == "data" {
+        if operands.is_empty() {
+            return Err("data needs at least one value".to_string());
+        }
+        let values = operands.into_iter().map(parse_expr).collect::<Result<_, _>>()?;
+        return Ok(Statement::Data(values));
+    }
+
+    let opcode = Opcode::from_mnemonic(mnemonic).ok_or_else(|| format!("unknown mnemonic `{}`", mnemonic))?;
+
+    if operands.len() != opcode.parameter_count() {
+        return Err(format!(
+            "`{}` takes {} operands, got {}",
+            mnemonic,
+            opcode.parameter_count(),
+            operands.len()
+        ));
+    }
+
+    let mut modes = [Mode::Position; 3];
+    let mut values = Vec::with_capacity(operands.len());
+    for (index, operand) in operands.into_iter().enumerate() {
+        let (mode, expr) = if let Some(rest) = operand.strip_prefix('#') {
+            (Mode::Immediate, rest)
+        } else if let Some(rest) = operand.strip_prefix('@') {
+            (Mode::Relative, rest)
+        } else {
+            (Mode::Position, operand)
+        };
+
+        if mode == Mode::Immediate && opcode.writes_memory() && index == opcode.parameter_count() - 1 {
+            return Err(format!("the output operand of `{}` can't be immediate", mnemonic));
+        }
+
+        modes[index] = mode;
+        values.push(parse_expr(expr)?);
+    }
+
+    Ok(Statement::Instruction {
+        instruction: Instruction::new(opcode, modes),
+        operands: values,
+    })
+}
+
+fn parse_expr(text: &str) -> Result<Expr, String> {
+    let mut terms = Vec::new();
+    let mut sign = 1;
+    let mut rest = text.trim();
+
+    loop {
+        while let Some(stripped) = rest.strip_prefix('-') {
+            sign = -sign;
+            rest = stripped.trim_start();
+        }
+
+        let end = rest.find(['+', '-']).unwrap_or(rest.len());
+        let term = rest[..end].trim();
+        if term.is_empty() {
+            return Err(format!("expected a value in `{}`", text.trim()));
+        }
+        terms.push((sign, parse_term(term)?));
+
+        rest = &rest[end..];
+        if rest.is_empty() {
+            return Ok(Expr { terms });
+        }
+
+        sign = if rest.starts_with('-') { -1 } else { 1 };
+        rest = rest[1..].trim_start();
+    }
+}
+
+fn parse_term(term: &str) -> Result<Term, String> {
+    if let Ok(number) = term.parse() {
+        Ok(Term::Number(number))
+    } else if is_identifier(term) {
+        Ok(Term::Symbol(term.to_string()))
+    } else {
+        Err(format!("invalid value `{}`", term))
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+fn evaluate(expr: &Expr, symbols: &HashMap<String, Symbol>, depth: usize) -> Result<i64, String> {
+    let mut total: i64 = 0;
+    for (sign, term) in &expr.terms {
+        let value = match term {
+            Term::Number(n) => *n,
+            Term::Symbol(name) => match symbols.get(name) {
+                Some(Symbol::Label(address)) => *address,
+                Some(Symbol::Constant(value)) => {
+                    if depth > symbols.len() {
+                        return Err(format!("constant `{}` is defined in terms of itself", name));
+                    }
+                    evaluate(value, symbols, depth + 1)?
+                }
+                None => return Err(format!("undefined symbol `{}`", name)),
+            },
+        };
+
+        total = value
+            .checked_mul(*sign)
+            .and_then(|v| total.checked_add(v))
+            .ok_or_else(|| "value overflows".to_string())?;
+    }
+
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assembles_day5_example() {
+        let program = assemble("mul x, #3, x\nx: data 33").unwrap();
+        assert_eq!(program, vec![1002, 4, 3, 4, 33]);
+    }
+
+    #[test]
+    fn assembles_comparison_example() {
+        let source = "
+            ; outputs 1 if the input is equal to 8
+                    in    value
+                    eq    value, target, value
+                    out   value
+                    hlt
+            value:  data  -1
+            target: data  8
+        ";
+        let program = assemble(source).unwrap();
+        assert_eq!(program, vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8]);
+    }
+
+    #[test]
+    fn resolves_constants_and_offsets() {
+        let source = "
+            base = end + 2
+            step = base - 1
+                    add #step, @-3, end+1
+            end:    hlt
+        ";
+        let program = assemble(source).unwrap();
+        assert_eq!(program, vec![2101, 5, -3, 5, 99]);
+    }
+
+    #[test]
+    fn reports_errors_with_line_numbers() {
+        assert_eq!(assemble("add 1, 2\n").unwrap_err(), Error { line: 1, message: "`add` takes 3 operands, got 2".to_string() });
+        assert_eq!(assemble("hlt\nout missing").unwrap_err().line, 2);
+        assert_eq!(assemble("in #0").unwrap_err().line, 1);
+        assert_eq!(assemble("a: hlt\na: hlt").unwrap_err().line, 2);
+        assert_eq!(assemble("nop").unwrap_err().message, "unknown mnemonic `nop`");
+        assert!(assemble("a = b\nb = a\nout a").is_err());
+    }
+}
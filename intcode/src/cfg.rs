@@ -0,0 +1,287 @@
+// Static control flow of a program, found by following it from address 0 without running it.
+// Only jumps with an immediate target can be followed, the others are recorded as indirect.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+
+use crate::disasm::{self, Item};
+use crate::{Instruction, Mode, Opcode};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Edge {
+    Jump(usize),
+    // Falling through to the next instruction, either because a jump wasn't taken or because
+    // it starts another block
+    Next(usize),
+    // A jump to an address computed at runtime
+    Indirect,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Block {
+    pub start: usize,
+    pub instructions: Vec<(usize, Instruction)>,
+    pub successors: Vec<Edge>,
+}
+
+impl Block {
+    // First address after the block
+    pub fn end(&self) -> usize {
+        self.instructions.last().map_or(self.start, |(address, instruction)| address + instruction.length())
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Graph {
+    pub blocks: BTreeMap<usize, Block>,
+    // Addresses of jumps whose target depends on memory
+    pub indirect_jumps: Vec<usize>,
+    // Addresses of instructions writing to a fixed address that holds code, or that execution
+    // reaches later. Writes in relative mode can't be known without running the program and
+    // aren't included.
+    pub self_modifying_writes: Vec<usize>,
+    // Addresses execution can reach that don't hold a valid instruction, usually because the
+    // program writes one there before getting to it
+    pub dead_ends: Vec<usize>,
+}
+
+impl Graph {
+    pub fn build(program: &[i64]) -> Graph {
+        let (instructions, leaders, dead_ends) = reachable(program);
+
+        let mut blocks = BTreeMap::new();
+        for &start in &leaders {
+            let mut block = Block { start, instructions: Vec::new(), successors: Vec::new() };
+            let mut address = start;
+            loop {
+                let instruction = instructions[&address];
+                block.instructions.push((address, instruction));
+                let next = address + instruction.length();
+
+                match instruction.opcode {
+                    Opcode::Halt => break,
+                    Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+                        block.successors = successors(program, address, instruction);
+                        break;
+                    }
+                    _ if leaders.contains(&next) => {
+                        block.successors.push(Edge::Next(next));
+                        break;
+                    }
+                    _ if !instructions.contains_key(&next) => {
+                        block.successors.push(Edge::Next(next));
+                        break;
+                    }
+                    _ => address = next,
+                }
+            }
+            blocks.insert(start, block);
+        }
+
+        let dead_ends = dead_ends.into_iter().collect();
+        let mut graph = Graph { blocks, indirect_jumps: Vec::new(), self_modifying_writes: Vec::new(), dead_ends };
+        let code = graph.code();
+        for (&address, instruction) in &instructions {
+            let opcode = instruction.opcode;
+            let is_jump = matches!(opcode, Opcode::JumpIfTrue | Opcode::JumpIfFalse);
+            if is_jump && successors(program, address, *instruction).contains(&Edge::Indirect) {
+                graph.indirect_jumps.push(address);
+            }
+
+            if opcode.writes_memory() {
+                let last = opcode.parameter_count() - 1;
+                let target = program[address + 1 + last];
+                let is_code = |target: usize| crate::machine::in_code(&code, target) || graph.dead_ends.contains(&target);
+                if instruction.modes[last] == Mode::Position && target >= 0 && is_code(target as usize) {
+                    graph.self_modifying_writes.push(address);
+                }
+            }
+        }
+        graph
+    }
+
+    // Sorted ranges of addresses holding reachable instructions, as (start, end)
+    pub fn code(&self) -> Vec<(usize, usize)> {
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+        let instructions = self.blocks.values().flat_map(|block| &block.instructions);
+        let mut spans: Vec<(usize, usize)> = instructions.map(|(address, instruction)| (*address, address + instruction.length())).collect();
+        spans.sort_unstable();
+
+        for (start, end) in spans {
+            match ranges.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => ranges.push((start, end)),
+            }
+        }
+        ranges
+    }
+
+    // Graphviz source with a node for every block. Blocks with an indirect jump or a write to
+    // the code are highlighted.
+    pub fn to_dot(&self, program: &[i64]) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph program {{").unwrap();
+        writeln!(dot, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+        if !self.indirect_jumps.is_empty() {
+            writeln!(dot, "    indirect [shape=diamond, label=\"computed address\"];").unwrap();
+        }
+
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for (address, _) in &block.instructions {
+                write!(label, "{}: {}", address, disasm::decode_at(program, *address)).unwrap();
+                if self.self_modifying_writes.contains(address) {
+                    label.push_str(" (writes code)");
+                }
+                label.push_str("\\l");
+            }
+
+            let flagged = block.instructions.iter().any(|(address, _)| self.indirect_jumps.contains(address) || self.self_modifying_writes.contains(address));
+            let color = if flagged { ", color=red" } else { "" };
+            writeln!(dot, "    b{} [label=\"{}\"{}];", block.start, label, color).unwrap();
+
+            for edge in &block.successors {
+                match edge {
+                    Edge::Jump(target) => writeln!(dot, "    b{} -> b{} [label=\"jump\"];", block.start, target).unwrap(),
+                    Edge::Next(next) => writeln!(dot, "    b{} -> b{};", block.start, next).unwrap(),
+                    Edge::Indirect => writeln!(dot, "    b{} -> indirect [style=dashed];", block.start).unwrap(),
+                }
+            }
+        }
+
+        for address in &self.dead_ends {
+            writeln!(dot, "    b{} [label=\"{}: not an instruction\", style=dashed];", address, address).unwrap();
+        }
+
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+}
+
+// Where execution can continue after a jump. A condition in immediate mode is known already,
+// so only one way is possible.
+fn successors(program: &[i64], address: usize, instruction: Instruction) -> Vec<Edge> {
+    let opcode = instruction.opcode;
+    let next = address + instruction.length();
+    let condition = program[address + 1];
+    let target = program[address + 2];
+
+    // A negative target fails at runtime, so there's nowhere to go
+    let jump = match instruction.modes[1] {
+        Mode::Immediate if target >= 0 => Some(Edge::Jump(target as usize)),
+        Mode::Immediate => None,
+        _ => Some(Edge::Indirect),
+    };
+    match instruction.modes[0] {
+        Mode::Immediate if (condition != 0) == (opcode == Opcode::JumpIfTrue) => jump.into_iter().collect(),
+        Mode::Immediate => vec![Edge::Next(next)],
+        _ => jump.into_iter().chain(Some(Edge::Next(next))).collect(),
+    }
+}
+
+// Instructions that can be reached by following the program from address 0, the addresses
+// blocks start at and the reachable addresses that don't hold an instruction
+fn reachable(program: &[i64]) -> (BTreeMap<usize, Instruction>, BTreeSet<usize>, BTreeSet<usize>) {
+    let mut instructions = BTreeMap::new();
+    let mut leaders = BTreeSet::new();
+    let mut dead_ends = BTreeSet::new();
+    leaders.insert(0);
+
+    let mut pending = vec![0];
+    while let Some(address) = pending.pop() {
+        if instructions.contains_key(&address) {
+            continue;
+        }
+        let instruction = match disasm::decode_at(program, address) {
+            Item::Instruction { instruction, .. } => instruction,
+            Item::Data(_) => {
+                dead_ends.insert(address);
+                continue;
+            }
+        };
+        instructions.insert(address, instruction);
+
+        match instruction.opcode {
+            Opcode::Halt => {}
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+                for edge in successors(program, address, instruction) {
+                    if let Edge::Jump(target) | Edge::Next(target) = edge {
+                        leaders.insert(target);
+                        pending.push(target);
+                    }
+                }
+            }
+            _ => pending.push(address + instruction.length()),
+        }
+    }
+
+    // Only addresses with an instruction can start a block
+    leaders.retain(|address| instructions.contains_key(address));
+    (instructions, leaders, dead_ends)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+
+    #[test]
+    fn splits_blocks_at_jumps() {
+        // Loops back to 2, the jump at 9 goes to a computed address
+        let program = vec![3, 20, 1001, 20, -1, 20, 1005, 20, 2, 6, 20, 20, 99];
+        let graph = Graph::build(&program);
+
+        assert_eq!(graph.blocks.keys().copied().collect::<Vec<_>>(), vec![0, 2, 9, 12]);
+        assert_eq!(graph.blocks[&0].successors, vec![Edge::Next(2)]);
+        assert_eq!(graph.blocks[&2].instructions.len(), 2);
+        assert_eq!(graph.blocks[&2].successors, vec![Edge::Jump(2), Edge::Next(9)]);
+        assert_eq!(graph.blocks[&9].successors, vec![Edge::Indirect, Edge::Next(12)]);
+        assert_eq!(graph.indirect_jumps, vec![9]);
+        assert_eq!(graph.code(), vec![(0, 13)]);
+    }
+
+    #[test]
+    fn follows_constant_conditions() {
+        let program = asm::assemble("jt #1, #skip\nout #1\nskip: jf #1, #4\nhlt").unwrap();
+        let graph = Graph::build(&program);
+        assert_eq!(graph.blocks.keys().copied().collect::<Vec<_>>(), vec![0, 5, 8]);
+        assert_eq!(graph.blocks[&0].successors, vec![Edge::Jump(5)]);
+        assert_eq!(graph.blocks[&5].successors, vec![Edge::Next(8)]);
+        assert_eq!(graph.code(), vec![(0, 3), (5, 9)]);
+    }
+
+    #[test]
+    fn flags_writes_to_code() {
+        let program = vec![1101, 1100, 1, 8, 1105, 1, 8, 99, 1102, 3, 4, 0, 99];
+        let graph = Graph::build(&program);
+        assert_eq!(graph.self_modifying_writes, vec![0, 8]);
+        assert!(graph.indirect_jumps.is_empty());
+
+        // Like the day5 diagnostics, which write the instruction that follows before running it
+        let program = vec![3, 9, 1, 9, 6, 6, 1100, 1, 0, 0, 99];
+        let graph = Graph::build(&program);
+        assert_eq!(graph.dead_ends, vec![6]);
+        assert_eq!(graph.self_modifying_writes, vec![2]);
+        assert_eq!(graph.blocks[&0].successors, vec![Edge::Next(6)]);
+    }
+
+    #[test]
+    fn exports_dot() {
+        let program = vec![3, 13, 8, 13, 14, 13, 1005, 13, 12, 6, 13, 13, 99, 0, 8];
+        let dot = Graph::build(&program).to_dot(&program);
+        assert_eq!(
+            dot,
+            "digraph program {
+    node [shape=box, fontname=\"monospace\"];
+    indirect [shape=diamond, label=\"computed address\"];
+    b0 [label=\"0: in 13\\l2: eq 13, 14, 13\\l6: jt 13, #12\\l\"];
+    b0 -> b12 [label=\"jump\"];
+    b0 -> b9;
+    b9 [label=\"9: jf 13, 13\\l\", color=red];
+    b9 -> indirect [style=dashed];
+    b9 -> b12;
+    b12 [label=\"12: hlt\\l\"];
+}
+"
+        );
+    }
+}
@@ -0,0 +1,139 @@
+// Translates a program into Rust source for `Machine::run_compiled`. Every address reachable
+// from the start without running the program gets a basic block, anything else, like jumps to
+// computed addresses or writes into the code, is left to the interpreter.
+use std::fmt::Write as _;
+
+use crate::cfg::{Block, Graph};
+use crate::disasm::Item;
+use crate::{format_program, Mode, Opcode};
+
+pub fn to_rust(program: &[i64]) -> String {
+    let graph = Graph::build(program);
+    let code = graph.code();
+
+    let mut source = String::new();
+    source.push_str("// Generated from an Intcode program by `intcode compile`, changes will be overwritten\n");
+    source.push_str("#![allow(clippy::all, unused)]\n\n");
+    source.push_str("use intcode::machine::{Cpu, Error, Exit};\n\n");
+    writeln!(source, "pub const PROGRAM: &[i64] = &[{}];\n", format_program(program).replace(',', ", ")).unwrap();
+    source.push_str("// Cells holding compiled instructions\n");
+    let ranges: Vec<String> = code.iter().map(|(start, end)| format!("({}, {})", start, end)).collect();
+    writeln!(source, "const CODE: &[(usize, usize)] = &[{}];\n", ranges.join(", ")).unwrap();
+
+    source.push_str("pub fn run(cpu: &mut Cpu, mut ip: usize) -> Result<Exit, Error> {\n");
+    source.push_str("    if !cpu.code_unchanged(PROGRAM, CODE) {\n");
+    source.push_str("        return Ok(Exit::Interpret { at: ip });\n");
+    source.push_str("    }\n\n");
+    source.push_str("    loop {\n");
+    source.push_str("        match ip {\n");
+    for block in graph.blocks.values() {
+        writeln!(source, "            {} => {{", block.start).unwrap();
+        block_source(&mut source, program, &graph, block);
+        source.push_str("            }\n");
+    }
+    source.push_str("            _ => return Ok(Exit::Interpret { at: ip }),\n");
+    source.push_str("        }\n");
+    source.push_str("    }\n");
+    source.push_str("}\n");
+    source
+}
+
+fn block_source(source: &mut String, program: &[i64], graph: &Graph, block: &Block) {
+    for &(address, instruction) in &block.instructions {
+        let parameters = &program[address + 1..address + instruction.length()];
+        let next = address + instruction.length();
+        let line = |source: &mut String, text: &str| writeln!(source, "                {}", text).unwrap();
+
+        let item = Item::Instruction { instruction, parameters: parameters.to_vec() };
+        line(source, &format!("// {}: {}", address, item));
+
+        let operand = |index: usize| match instruction.modes[index] {
+            Mode::Immediate => parameters[index].to_string(),
+            Mode::Position => format!("cpu.load({}, {})?", address, parameters[index]),
+            Mode::Relative => format!("cpu.load({}, cpu.relative_base.wrapping_add({}))?", address, parameters[index]),
+        };
+        let target = |index: usize| match instruction.modes[index] {
+            Mode::Relative => format!("cpu.address({}, cpu.relative_base.wrapping_add({}))?", address, parameters[index]),
+            _ => format!("cpu.address({}, {})?", address, parameters[index]),
+        };
+
+        let opcode = instruction.opcode;
+        match opcode {
+            Opcode::Add | Opcode::Multiply | Opcode::LessThan | Opcode::Equals => {
+                line(source, &format!("let a: i64 = {};", operand(0)));
+                line(source, &format!("let b: i64 = {};", operand(1)));
+                line(source, &format!("let t = {};", target(2)));
+                let value = match opcode {
+                    Opcode::Add => "a.wrapping_add(b)",
+                    Opcode::Multiply => "a.wrapping_mul(b)",
+                    Opcode::LessThan => "(a < b) as i64",
+                    _ => "(a == b) as i64",
+                };
+                line(source, &format!("cpu.store(t, {});", value));
+            }
+            Opcode::Input => {
+                line(source, "if cpu.inputs.is_empty() {");
+                line(source, &format!("    return Ok(Exit::Interpret {{ at: {} }});", address));
+                line(source, "}");
+                line(source, &format!("let t = {};", target(0)));
+                line(source, "let value = cpu.inputs.pop_front().unwrap();");
+                line(source, "cpu.store(t, value);");
+            }
+            Opcode::Output => {
+                line(source, &format!("let a: i64 = {};", operand(0)));
+                line(source, "cpu.outputs.push_back(a);");
+            }
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+                line(source, &format!("let a: i64 = {};", operand(0)));
+                line(source, &format!("let b: i64 = {};", operand(1)));
+                line(source, &format!("if a {} 0 {{", if opcode == Opcode::JumpIfTrue { "!=" } else { "==" }));
+                line(source, &format!("    ip = cpu.address({}, b)?;", address));
+                line(source, "    cpu.steps += 1;");
+                line(source, "    continue;");
+                line(source, "}");
+            }
+            Opcode::AdjustRelativeBase => {
+                line(source, &format!("let a: i64 = {};", operand(0)));
+                line(source, "cpu.relative_base = cpu.relative_base.wrapping_add(a);");
+            }
+            Opcode::Halt => {
+                line(source, "cpu.steps += 1;");
+                line(source, &format!("return Ok(Exit::Halted {{ at: {} }});", address));
+                return;
+            }
+        }
+        line(source, "cpu.steps += 1;");
+
+        // Writing to the code makes the rest of the block out of date
+        if opcode.writes_memory() {
+            let index = opcode.parameter_count() - 1;
+            let interpret = format!("return Ok(Exit::Interpret {{ at: {} }});", next);
+            match instruction.modes[index] {
+                Mode::Relative => {
+                    line(source, "if intcode::machine::in_code(CODE, t) {");
+                    line(source, &format!("    {}", interpret));
+                    line(source, "}");
+                }
+                _ if graph.self_modifying_writes.contains(&address) => {
+                    line(source, &interpret);
+                    return;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    writeln!(source, "                ip = {};", block.end()).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hands_writes_to_code_to_the_interpreter() {
+        let source = to_rust(&[1101, 1100, 1, 8, 1105, 1, 8, 99, 1102, 3, 4, 0, 99]);
+        assert!(source.contains("                // 0: add #1100, #1, 8\n"));
+        assert!(source.contains("                cpu.steps += 1;\n                return Ok(Exit::Interpret { at: 4 });\n"));
+    }
+}
@@ -0,0 +1,410 @@
+use std::collections::BTreeSet;
+use std::io::{self, BufRead};
+
+use crate::disasm;
+use crate::machine::{Error, Machine, State, Step, Write};
+use crate::Opcode;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Stop {
+    Stepped,
+    Breakpoint(usize),
+    OpcodeBreakpoint(Opcode),
+    Watchpoint(Write),
+    AwaitingInput,
+    Halted,
+}
+
+pub struct Debugger {
+    machine: Machine,
+    breakpoints: BTreeSet<usize>,
+    opcode_breakpoints: BTreeSet<Opcode>,
+    watchpoints: BTreeSet<usize>,
+}
+
+impl Debugger {
+    // History is recorded from here on so the debugger can step backwards
+    pub fn new(mut machine: Machine) -> Debugger {
+        machine.record_history();
+        Debugger {
+            machine,
+            breakpoints: BTreeSet::new(),
+            opcode_breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+        }
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
+
+    pub fn add_breakpoint(&mut self, address: usize) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn add_opcode_breakpoint(&mut self, opcode: Opcode) {
+        self.opcode_breakpoints.insert(opcode);
+    }
+
+    pub fn add_watchpoint(&mut self, address: usize) {
+        self.watchpoints.insert(address);
+    }
+
+    // Executes one instruction, calling `on_step` with what it did
+    pub fn step<F: FnMut(&Step)>(&mut self, mut on_step: F) -> Result<Stop, Error> {
+        match self.machine.step()? {
+            Some(step) => {
+                on_step(&step);
+                match step.write {
+                    Some(write) if self.watchpoints.contains(&write.address) => Ok(Stop::Watchpoint(write)),
+                    _ => Ok(Stop::Stepped),
+                }
+            }
+            None => Ok(self.stopped()),
+        }
+    }
+
+    // Runs until a breakpoint is reached, a watched cell is written or the machine stops. The
+    // first instruction is always executed, so resuming from a breakpoint moves past it.
+    pub fn resume<F: FnMut(&Step)>(&mut self, mut on_step: F) -> Result<Stop, Error> {
+        let mut first = true;
+        loop {
+            if !first {
+                if let Some(stop) = self.breakpoint_at_ip() {
+                    return Ok(stop);
+                }
+            }
+            first = false;
+
+            match self.step(&mut on_step)? {
+                Stop::Stepped => continue,
+                stop => return Ok(stop),
+            }
+        }
+    }
+
+    fn breakpoint_at_ip(&self) -> Option<Stop> {
+        let ip = self.machine.ip();
+        if self.breakpoints.contains(&ip) {
+            return Some(Stop::Breakpoint(ip));
+        }
+
+        match disasm::decode_at(self.machine.memory(), ip) {
+            disasm::Item::Instruction { instruction, .. } if self.opcode_breakpoints.contains(&instruction.opcode) => {
+                Some(Stop::OpcodeBreakpoint(instruction.opcode))
+            }
+            _ => None,
+        }
+    }
+
+    fn stopped(&self) -> Stop {
+        match self.machine.state() {
+            State::Halted => Stop::Halted,
+            _ => Stop::AwaitingInput,
+        }
+    }
+
+    // Reads commands until `quit` or the end of the input
+    pub fn repl<R: BufRead, W: io::Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        write!(output, "> ")?;
+        output.flush()?;
+        for line in input.lines() {
+            if !self.execute(line?.trim(), &mut output)? {
+                break;
+            }
+            write!(output, "> ")?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+
+    // Runs a single command, returns false if the debugger should exit
+    pub fn execute<W: io::Write>(&mut self, command: &str, output: &mut W) -> io::Result<bool> {
+        let parts: Vec<&str> = command.split_whitespace().collect();
+        let args = parts.get(1..).unwrap_or(&[]);
+
+        match parts.first().copied() {
+            None => {}
+            Some("s") | Some("step") => match parse_count(args) {
+                Some(count) => {
+                    for _ in 0..count {
+                        let result = self.step(|step| print_output(step, output));
+                        let stop = self.report(result, output)?;
+                        if stop != Some(Stop::Stepped) {
+                            break;
+                        }
+                    }
+                    self.print_location(output)?;
+                }
+                None => writeln!(output, "Usage: step [count]")?,
+            },
+            Some("bs") | Some("back") => match parse_count(args) {
+                Some(count) => {
+                    let undone = (0..count).take_while(|_| self.machine.step_back().is_some()).count();
+                    if undone < count {
+                        writeln!(output, "At the start of the history")?;
+                    }
+                    self.print_location(output)?;
+                }
+                None => writeln!(output, "Usage: back [count]")?,
+            },
+            Some("rewind") => match args {
+                [steps] => match steps.parse() {
+                    Ok(steps) if self.machine.rewind_to(steps) => self.print_location(output)?,
+                    Ok(_) => {
+                        let start = self.machine.history_start().unwrap_or(0);
+                        writeln!(output, "History covers steps {} to {}", start, self.machine.steps())?
+                    }
+                    Err(_) => writeln!(output, "Not a step: {}", steps)?,
+                },
+                _ => writeln!(output, "Usage: rewind <step>")?,
+            },
+            Some("lw") | Some("lastwrite") => match args {
+                [address] => match address.parse() {
+                    Ok(address) => match self.machine.rewind_to_write(address) {
+                        Some(write) => {
+                            writeln!(output, "Step {} wrote {}: {} -> {}", self.machine.steps(), write.address, write.old, write.new)?;
+                            self.print_location(output)?;
+                        }
+                        None => writeln!(output, "No recorded write to {}", address)?,
+                    },
+                    Err(_) => writeln!(output, "Not an address: {}", address)?,
+                },
+                _ => writeln!(output, "Usage: lastwrite <address>")?,
+            },
+            Some("c") | Some("continue") => {
+                let result = self.resume(|step| print_output(step, output));
+                self.report(result, output)?;
+                self.print_location(output)?;
+            }
+            Some("b") | Some("break") => match args {
+                [target] => match parse_target(target) {
+                    Some(Target::Address(address)) => {
+                        self.add_breakpoint(address);
+                        writeln!(output, "Breakpoint at {}", address)?;
+                    }
+                    Some(Target::Opcode(opcode)) => {
+                        self.add_opcode_breakpoint(opcode);
+                        writeln!(output, "Breakpoint on {}", opcode)?;
+                    }
+                    None => writeln!(output, "Not an address or mnemonic: {}", target)?,
+                },
+                _ => writeln!(output, "Usage: break <address|mnemonic>")?,
+            },
+            Some("w") | Some("watch") => match args {
+                [address] => match address.parse() {
+                    Ok(address) => {
+                        self.add_watchpoint(address);
+                        writeln!(output, "Watching {}", address)?;
+                    }
+                    Err(_) => writeln!(output, "Not an address: {}", address)?,
+                },
+                _ => writeln!(output, "Usage: watch <address>")?,
+            },
+            Some("d") | Some("delete") => match args {
+                [target] => {
+                    let removed = match parse_target(target) {
+                        Some(Target::Address(address)) => {
+                            self.breakpoints.remove(&address) | self.watchpoints.remove(&address)
+                        }
+                        Some(Target::Opcode(opcode)) => self.opcode_breakpoints.remove(&opcode),
+                        None => false,
+                    };
+                    if !removed {
+                        writeln!(output, "No breakpoint or watchpoint on {}", target)?;
+                    }
+                }
+                _ => writeln!(output, "Usage: delete <address|mnemonic>")?,
+            },
+            Some("info") => {
+                writeln!(output, "Breakpoints: {:?}", self.breakpoints)?;
+                writeln!(output, "Opcode breakpoints: {:?}", self.opcode_breakpoints.iter().map(|op| op.mnemonic()).collect::<Vec<_>>())?;
+                writeln!(output, "Watchpoints: {:?}", self.watchpoints)?;
+            }
+            Some("m") | Some("mem") => {
+                let numbers: Result<Vec<usize>, _> = args.iter().map(|a| a.parse()).collect();
+                match numbers.as_deref() {
+                    Ok([start]) => self.dump_memory(*start, 8, output)?,
+                    Ok([start, count]) => self.dump_memory(*start, *count, output)?,
+                    _ => writeln!(output, "Usage: mem <address> [count]")?,
+                }
+            }
+            Some("r") | Some("regs") => {
+                writeln!(output, "ip: {}  relative base: {}", self.machine.ip(), self.machine.relative_base())?;
+                self.print_location(output)?;
+            }
+            Some("i") | Some("input") => {
+                let values: Result<Vec<i64>, _> = args.iter().map(|a| a.parse()).collect();
+                match values {
+                    Ok(ref values) if !values.is_empty() => self.machine.extend_inputs(values.iter().copied()),
+                    _ => writeln!(output, "Usage: input <value>...")?,
+                }
+            }
+            Some("q") | Some("quit") => return Ok(false),
+            Some("h") | Some("help") => writeln!(output, "{}", HELP)?,
+            Some(other) => writeln!(output, "Unknown command {}, try help", other)?,
+        }
+
+        Ok(true)
+    }
+
+    fn report<W: io::Write>(&self, result: Result<Stop, Error>, output: &mut W) -> io::Result<Option<Stop>> {
+        match result {
+            Ok(stop) => {
+                match stop {
+                    Stop::Stepped => {}
+                    Stop::Breakpoint(address) => writeln!(output, "Breakpoint at {}", address)?,
+                    Stop::OpcodeBreakpoint(opcode) => writeln!(output, "Breakpoint on {}", opcode)?,
+                    Stop::Watchpoint(write) => writeln!(output, "Watchpoint {}: {} -> {}", write.address, write.old, write.new)?,
+                    Stop::AwaitingInput => writeln!(output, "Waiting for input")?,
+                    Stop::Halted => writeln!(output, "Halted")?,
+                }
+                Ok(Some(stop))
+            }
+            Err(e) => {
+                writeln!(output, "Error: {}", e)?;
+                Ok(None)
+            }
+        }
+    }
+
+    fn print_location<W: io::Write>(&self, output: &mut W) -> io::Result<()> {
+        let ip = self.machine.ip();
+        writeln!(output, "{:>6}: {}", ip, disasm::decode_at(self.machine.memory(), ip))
+    }
+
+    fn dump_memory<W: io::Write>(&self, start: usize, count: usize, output: &mut W) -> io::Result<()> {
+        let end = match start.checked_add(count) {
+            Some(end) => end,
+            None => return writeln!(output, "Not a range: {} cells from {}", count, start),
+        };
+        for row in (start..end).step_by(8) {
+            let cells: Vec<String> = (row..row.saturating_add(8).min(end)).map(|a| self.machine.read(a).to_string()).collect();
+            writeln!(output, "{:>6}: {}", row, cells.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+enum Target {
+    Address(usize),
+    Opcode(Opcode),
+}
+
+fn parse_target(target: &str) -> Option<Target> {
+    target
+        .parse()
+        .ok()
+        .map(Target::Address)
+        .or_else(|| Opcode::from_mnemonic(target).map(Target::Opcode))
+}
+
+fn parse_count(args: &[&str]) -> Option<usize> {
+    match args {
+        [] => Some(1),
+        [count] => count.parse().ok(),
+        _ => None,
+    }
+}
+
+fn print_output<W: io::Write>(step: &Step, output: &mut W) {
+    if let Some(value) = step.output {
+        // Errors writing here will show up on the next write to the same output
+        let _ = writeln!(output, "Output: {}", value);
+    }
+}
+
+const HELP: &str = "\
+step [count]              execute count instructions (default 1)
+back [count]              undo count instructions (default 1)
+rewind <step>             undo instructions until only step instructions have run
+lastwrite <address>       undo instructions until just before the last write to the cell
+continue                  run until a breakpoint, watchpoint, halt or missing input
+break <address|mnemonic>  stop before executing an address or any instruction with the opcode
+watch <address>           stop after the cell is written
+delete <address|mnemonic> remove breakpoints and watchpoints
+info                      list breakpoints and watchpoints
+mem <address> [count]     dump memory
+regs                      show the instruction pointer and relative base
+input <value>...          queue input values
+quit";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(program: Vec<i64>, commands: &str) -> String {
+        let mut debugger = Debugger::new(Machine::new(program));
+        let mut output = Vec::new();
+        debugger.repl(commands.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        output.lines().map(|line| format!("{}\n", line.trim_start_matches("> "))).filter(|line| line != "\n").collect()
+    }
+
+    fn comparison_program() -> Vec<i64> {
+        vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8]
+    }
+
+    #[test]
+    fn steps_and_shows_registers() {
+        let output = session(comparison_program(), "input 8\nstep\nregs\n");
+        assert_eq!(output, "     2: eq 9, 10, 9\nip: 2  relative base: 0\n     2: eq 9, 10, 9\n");
+    }
+
+    #[test]
+    fn stops_at_breakpoints() {
+        let output = session(comparison_program(), "break 6\ninput 7\ncontinue\ncontinue\n");
+        assert_eq!(output, "Breakpoint at 6\nBreakpoint at 6\n     6: out 9\nOutput: 0\nHalted\n     8: hlt\n");
+    }
+
+    #[test]
+    fn stops_on_opcode_breakpoints() {
+        let output = session(comparison_program(), "break out\ni 8\nc\n");
+        assert_eq!(output, "Breakpoint on out\nBreakpoint on out\n     6: out 9\n");
+    }
+
+    #[test]
+    fn stops_at_watchpoints() {
+        let output = session(comparison_program(), "watch 9\ni 8\nc\nc\nmem 8 3\n");
+        assert_eq!(
+            output,
+            "Watching 9\nWatchpoint 9: -1 -> 8\n     2: eq 9, 10, 9\nWatchpoint 9: 8 -> 1\n     6: out 9\n     8: 99 1 8\n"
+        );
+    }
+
+    #[test]
+    fn steps_backwards() {
+        let output = session(comparison_program(), "i 8\nc\nback 2\nmem 9 1\nrewind 1\nback 5\nrewind 3\nc\n");
+        assert_eq!(
+            output,
+            "Output: 1\nHalted\n     8: hlt\n     6: out 9\n     9: 1\n     2: eq 9, 10, 9\nAt the start of the history\n     0: in 9\n\
+             History covers steps 0 to 0\nOutput: 1\nHalted\n     8: hlt\n"
+        );
+    }
+
+    #[test]
+    fn rewinds_to_the_last_write() {
+        let output = session(comparison_program(), "i 8\nc\nlastwrite 9\nlastwrite 9\nlastwrite 9\nlastwrite 0\n");
+        assert_eq!(
+            output,
+            "Output: 1\nHalted\n     8: hlt\nStep 1 wrote 9: 8 -> 1\n     2: eq 9, 10, 9\nStep 0 wrote 9: -1 -> 8\n     0: in 9\n\
+             No recorded write to 9\nNo recorded write to 0\n"
+        );
+    }
+
+    #[test]
+    fn rejects_ranges_past_the_last_address() {
+        let output = session(comparison_program(), &format!("mem 1 {}\nmem {} 1\nmem 9 2\n", usize::MAX, usize::MAX - 1));
+        assert_eq!(output, format!("Not a range: {} cells from 1\n{}: 0\n     9: -1 8\n", usize::MAX, usize::MAX - 1));
+    }
+
+    #[test]
+    fn reports_missing_input() {
+        let output = session(comparison_program(), "c\nquit\nstep\n");
+        assert_eq!(output, "Waiting for input\n     0: in 9\n");
+    }
+}
@@ -0,0 +1,118 @@
+// Turns a program back into source the assembler accepts. Cells that don't decode as a
+// complete instruction are written out as `data`, so assembling the output always gives
+// back the original program.
+use std::fmt;
+
+use crate::Instruction;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Item {
+    Instruction { instruction: Instruction, parameters: Vec<i64> },
+    Data(i64),
+}
+
+impl Item {
+    pub fn length(&self) -> usize {
+        match self {
+            Item::Instruction { instruction, .. } => instruction.length(),
+            Item::Data(_) => 1,
+        }
+    }
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Item::Instruction { instruction, parameters } => {
+                write!(f, "{}", instruction.opcode)?;
+                for (index, (mode, value)) in instruction.modes.iter().zip(parameters).enumerate() {
+                    let separator = if index == 0 { " " } else { ", " };
+                    write!(f, "{}{}{}", separator, mode.prefix(), value)?;
+                }
+                Ok(())
+            }
+            Item::Data(value) => write!(f, "data {}", value),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Line {
+    pub address: usize,
+    pub item: Item,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:<28}; {}", self.item.to_string(), self.address)
+    }
+}
+
+pub fn decode_at(program: &[i64], address: usize) -> Item {
+    let word = program.get(address).copied().unwrap_or(0);
+    match Instruction::decode(word) {
+        Some(instruction) if address + instruction.length() <= program.len() => Item::Instruction {
+            instruction,
+            parameters: program[address + 1..address + instruction.length()].to_vec(),
+        },
+        _ => Item::Data(word),
+    }
+}
+
+pub fn disassemble(program: &[i64]) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut address = 0;
+
+    while address < program.len() {
+        let item = decode_at(program, address);
+        let length = item.length();
+        lines.push(Line { address, item });
+        address += length;
+    }
+
+    lines
+}
+
+pub fn to_source(program: &[i64]) -> String {
+    disassemble(program).iter().map(|line| format!("{}\n", line)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    fn round_trip(program: Vec<i64>) {
+        let source = to_source(&program);
+        assert_eq!(assemble(&source).unwrap(), program, "source was:\n{}", source);
+    }
+
+    #[test]
+    fn disassembles_instructions() {
+        let lines = disassemble(&[1002, 4, 3, 4, 33]);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].item.to_string(), "mul 4, #3, 4");
+        assert_eq!(lines[1].item.to_string(), "data 33");
+        assert_eq!(lines[1].address, 4);
+    }
+
+    #[test]
+    fn truncated_instruction_is_data() {
+        let lines = disassemble(&[1, 0, 0]);
+        assert_eq!(lines.iter().map(|l| l.item.clone()).collect::<Vec<_>>(), vec![Item::Data(1), Item::Data(0), Item::Data(0)]);
+    }
+
+    #[test]
+    fn round_trips_examples() {
+        round_trip(vec![1, 1, 1, 4, 99, 5, 6, 0, 99]);
+        round_trip(vec![3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, -1, 0, 1, 9]);
+        round_trip(vec![109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99]);
+        round_trip(vec![3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0, 0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4, 20, 1105, 1, 46, 98, 99]);
+        round_trip(vec![3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1, 28, 1005, 28, 6, 99, 0, 0, 5]);
+    }
+
+    #[test]
+    fn round_trips_odd_words() {
+        round_trip(vec![-5, 100_000, 10_101, 199, 0, 42]);
+    }
+}
@@ -0,0 +1,248 @@
+// Random programs for property tests, and for comparing other interpreters against `Machine`.
+// Generated programs are valid and always halt: reads and writes stay in a data area after the
+// code, and jumps only go forward to the start of an instruction.
+use std::ops::RangeInclusive;
+
+use crate::machine::Error;
+use crate::{Instruction, Limits, Machine, Mode, Opcode, State};
+
+// xorshift64*, good enough to pick instructions and small enough to not need a dependency.
+// The same seed always gives the same numbers, so a failing seed can be replayed.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // The state must never be 0
+        Rng { state: seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1 }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // A number in 0..n, n must not be 0
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    pub fn range(&mut self, range: RangeInclusive<i64>) -> i64 {
+        let width = range.end().wrapping_sub(*range.start()) as u64;
+        match width.checked_add(1) {
+            Some(n) => range.start().wrapping_add(self.below(n) as i64),
+            None => self.next_u64() as i64,
+        }
+    }
+
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len() as u64) as usize]
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Generator {
+    opcodes: Vec<Opcode>,
+    modes: Vec<Mode>,
+    instructions: usize,
+    data: usize,
+    values: RangeInclusive<i64>,
+}
+
+impl Generator {
+    // Programs built from the given opcodes and ended by a halt. Relative mode and adjusting the
+    // relative base are left out, the generator can't keep relative addresses in the data area.
+    pub fn new(opcodes: &[Opcode]) -> Generator {
+        let opcodes = opcodes.iter().copied().filter(|opcode| !matches!(opcode, Opcode::Halt | Opcode::AdjustRelativeBase)).collect();
+        Generator { opcodes, modes: vec![Mode::Position, Mode::Immediate], instructions: 16, data: 8, values: -100..=100 }
+    }
+
+    // Modes for parameters that are read, written parameters are always in position mode and
+    // jump targets in immediate mode. Defaults to position and immediate.
+    pub fn modes(mut self, modes: &[Mode]) -> Generator {
+        self.modes = modes.iter().copied().filter(|&mode| mode != Mode::Relative).collect();
+        self
+    }
+
+    // Most instructions before the halt, defaults to 16
+    pub fn instructions(mut self, instructions: usize) -> Generator {
+        self.instructions = instructions;
+        self
+    }
+
+    // Cells after the code, defaults to 8
+    pub fn data(mut self, data: usize) -> Generator {
+        self.data = data.max(1);
+        self
+    }
+
+    // Initial data and immediate values, defaults to -100..=100
+    pub fn values(mut self, values: RangeInclusive<i64>) -> Generator {
+        self.values = values;
+        self
+    }
+
+    pub fn generate(&self, rng: &mut Rng) -> Vec<i64> {
+        let count = if self.opcodes.is_empty() { 0 } else { rng.below(self.instructions as u64 + 1) as usize };
+        let instructions: Vec<Opcode> = (0..count).map(|_| *rng.choose(&self.opcodes)).collect();
+
+        let mut starts = Vec::with_capacity(count + 1);
+        let mut address = 0;
+        for opcode in &instructions {
+            starts.push(address);
+            address += 1 + opcode.parameter_count();
+        }
+        // The halt
+        starts.push(address);
+        let data_start = address + 1;
+
+        let mut program = Vec::with_capacity(data_start + self.data);
+        for (index, &opcode) in instructions.iter().enumerate() {
+            let mut modes = [Mode::Position; 3];
+            let mut parameters = Vec::with_capacity(3);
+            for (parameter, mode) in modes.iter_mut().enumerate().take(opcode.parameter_count()) {
+                let is_target = matches!(opcode, Opcode::JumpIfTrue | Opcode::JumpIfFalse) && parameter == 1;
+                let writes = opcode.writes_memory() && parameter == opcode.parameter_count() - 1;
+                *mode = if is_target {
+                    Mode::Immediate
+                } else if writes {
+                    Mode::Position
+                } else {
+                    *rng.choose(&self.modes)
+                };
+
+                parameters.push(match *mode {
+                    _ if is_target => *rng.choose(&starts[index + 1..]) as i64,
+                    Mode::Immediate => rng.range(self.values.clone()),
+                    _ => (data_start + rng.below(self.data as u64) as usize) as i64,
+                });
+            }
+            program.push(Instruction::new(opcode, modes).encode());
+            program.extend(parameters);
+        }
+        program.push(Opcode::Halt.code());
+        program.extend((0..self.data).map(|_| rng.range(self.values.clone())));
+        program
+    }
+}
+
+// How a program ran on `Machine`, to compare other interpreters with
+#[derive(Clone, Debug)]
+pub struct Run {
+    pub result: Result<State, Error>,
+    pub memory: Vec<i64>,
+    pub outputs: Vec<i64>,
+    // Largest magnitude of any value written or output. Interpreters with narrower cells than
+    // `Machine` can only be expected to agree on runs where this fits.
+    pub largest: u64,
+}
+
+pub fn run(program: &[i64], inputs: &[i64], limits: Limits) -> Run {
+    let mut machine = Machine::new(program.to_vec());
+    machine.extend_inputs(inputs.iter().copied());
+
+    let mut largest = 0;
+    let result = machine.run_limited_with(limits, |step| {
+        let values = step.write.map(|write| write.new).into_iter().chain(step.output);
+        largest = values.fold(largest, |largest, value| largest.max(value.unsigned_abs()));
+    });
+    Run { result, memory: machine.memory().to_vec(), outputs: machine.take_outputs(), largest }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OPCODES;
+
+    const SEEDS: u64 = 500;
+
+    fn limits() -> Limits {
+        Limits { max_steps: Some(10_000), ..Limits::default() }
+    }
+
+    #[test]
+    fn generated_programs_halt() {
+        let generator = Generator::new(&OPCODES);
+        for seed in 0..SEEDS {
+            let program = generator.generate(&mut Rng::new(seed));
+            let inputs: Vec<i64> = (0..program.len() as i64).collect();
+            let run = run(&program, &inputs, limits());
+            assert_eq!(run.result, Ok(State::Halted), "seed {}: {:?}", seed, program);
+        }
+    }
+
+    #[test]
+    fn random_words_dont_panic() {
+        // Mostly valid instructions in any mode, with arbitrary parameters. Running them can
+        // fail, loop or wait for input, but must not bring the machine down.
+        for seed in 0..SEEDS {
+            let mut rng = Rng::new(seed);
+            let length = 1 + rng.below(40) as usize;
+            let program: Vec<i64> = (0..length)
+                .map(|_| match rng.below(4) {
+                    0 => rng.next_u64() as i64,
+                    1 => rng.range(-10..=10),
+                    _ => {
+                        let modes = [*rng.choose(&[Mode::Position, Mode::Immediate, Mode::Relative]); 3];
+                        Instruction::new(*rng.choose(&OPCODES), modes).encode()
+                    }
+                })
+                .collect();
+
+            let mut machine = Machine::new(program);
+            machine.extend_inputs(rng.range(-5..=5)..6);
+            let _ = machine.run_limited(limits());
+        }
+    }
+
+    #[test]
+    fn invalid_opcodes_are_errors() {
+        let mut rng = Rng::new(1);
+        let mut checked = 0;
+        while checked < SEEDS {
+            let word = if rng.below(2) == 0 { rng.range(-1000..=100_000) } else { rng.next_u64() as i64 };
+            if Instruction::decode(word).is_some() {
+                continue;
+            }
+            checked += 1;
+            let mut machine = Machine::new(vec![word, 0, 0, 0]);
+            assert_eq!(machine.step(), Err(Error::InvalidInstruction { address: 0, word }));
+            assert_eq!(machine.ip(), 0);
+        }
+    }
+
+    #[test]
+    fn replays_deterministically() {
+        let generator = Generator::new(&OPCODES).instructions(30);
+        for seed in 0..SEEDS {
+            let program = generator.generate(&mut Rng::new(seed));
+            assert_eq!(program, generator.generate(&mut Rng::new(seed)));
+
+            let mut first = Machine::new(program.clone());
+            first.extend_inputs(0..10);
+            let start = first.snapshot();
+            first.run().unwrap();
+
+            // Running in single steps from a snapshot taken halfway gives the same end state
+            let mut second = Machine::new(program.clone());
+            second.extend_inputs(0..10);
+            second.run_limited(Limits { max_steps: Some(first.steps() / 2), ..Limits::default() }).ok();
+            let mut resumed = Machine::new(Vec::new());
+            resumed.restore(&second.snapshot());
+            while resumed.step().unwrap().is_some() {}
+            assert_eq!(resumed.snapshot(), first.snapshot(), "seed {}", seed);
+
+            // And undoing every step gets back to the start
+            let mut third = Machine::new(program);
+            third.extend_inputs(0..10);
+            third.record_history();
+            third.run().unwrap();
+            assert!(third.rewind_to(0));
+            assert_eq!(third.snapshot(), start, "seed {}", seed);
+        }
+    }
+}
@@ -0,0 +1,220 @@
+// Compact binary form of a program or memory image, so it doesn't have to be parsed from text
+// every time. After the magic bytes comes the format version, the number of bits needed to hold
+// every cell, the address to start at and then the cells, all as varints.
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::machine::MAX_MEMORY;
+use crate::{format_program, parse_program, varint, Machine};
+
+const MAGIC: &[u8; 3] = b"ICI";
+const VERSION: u8 = 1;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Image {
+    pub memory: Vec<i64>,
+    pub entry: usize,
+}
+
+impl Image {
+    pub fn new(memory: Vec<i64>) -> Image {
+        Image { memory, entry: 0 }
+    }
+
+    // The memory of a machine, starting where it's at. Registers and I/O aren't included, a
+    // `Snapshot` has those.
+    pub fn of(machine: &Machine) -> Image {
+        Image { memory: machine.memory().to_vec(), entry: machine.ip() }
+    }
+
+    pub fn machine(&self) -> Machine {
+        Machine::starting_at(self.memory.clone(), self.entry)
+    }
+
+    // 8, 16, 32 or 64, for interpreters with narrower cells to check whether they can run it
+    pub fn cell_bits(&self) -> u8 {
+        let fits = |bits: u32| self.memory.iter().all(|&cell| cell >= -(1 << (bits - 1)) && cell < 1 << (bits - 1));
+        [8, 16, 32].iter().copied().find(|&bits| fits(bits)).unwrap_or(64) as u8
+    }
+
+    // The comma separated format, which has no way to say where to start
+    pub fn to_text(&self) -> String {
+        format_program(&self.memory)
+    }
+
+    pub fn from_text(text: &str) -> Result<Image, std::num::ParseIntError> {
+        Ok(Image::new(parse_program(text)?))
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION, self.cell_bits()])?;
+        varint::write_u64(&mut writer, self.entry as u64)?;
+        varint::write_u64(&mut writer, self.memory.len() as u64)?;
+        for cell in &self.memory {
+            varint::write_i64(&mut writer, *cell)?;
+        }
+        writer.flush()
+    }
+
+    pub fn read_from<R: Read>(mut reader: R) -> io::Result<Image> {
+        let mut header = [0u8; 5];
+        reader.read_exact(&mut header)?;
+        if &header[..3] != MAGIC {
+            return Err(invalid("not an image"));
+        }
+        if header[3] != VERSION {
+            return Err(invalid(&format!("unsupported image version {}", header[3])));
+        }
+        let bits = header[4];
+        if ![8, 16, 32, 64].contains(&bits) {
+            return Err(invalid(&format!("unsupported cell width {}", bits)));
+        }
+
+        let entry = varint::expect_u64(&mut reader)? as usize;
+        let count = varint::expect_u64(&mut reader)? as usize;
+        if count > MAX_MEMORY {
+            return Err(invalid("image is too big"));
+        }
+        let memory = (0..count).map(|_| varint::expect_i64(&mut reader)).collect::<io::Result<Vec<i64>>>()?;
+
+        let image = Image { memory, entry };
+        if image.cell_bits() > bits {
+            return Err(invalid("cell is wider than the header says"));
+        }
+        Ok(image)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Image> {
+        Image::read_from(BufReader::new(File::open(path)?))
+    }
+}
+
+// Whether the bytes start like an image rather than a text program
+pub fn is_image(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+// Reads a program in either format
+pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Image> {
+    let bytes = fs::read(path)?;
+    if is_image(&bytes) {
+        return Image::read_from(&bytes[..]);
+    }
+    let text = String::from_utf8(bytes).map_err(|_| invalid("neither an image nor a text program"))?;
+    Image::from_text(&text).map_err(|e| invalid(&e.to_string()))
+}
+
+// Reads a text program through an image next to it, with `.ici` added to the name. The image is
+// written the first time and used for as long as it's newer than the text. Failing to write it
+// only means the text is parsed again next time.
+pub fn read_cached<P: AsRef<Path>>(path: P) -> io::Result<Vec<i64>> {
+    let path = path.as_ref();
+    let cache = cache_path(path);
+
+    let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified());
+    if let (Ok(text), Ok(image)) = (modified(path), modified(&cache)) {
+        if image >= text {
+            if let Ok(image) = Image::load(&cache) {
+                return Ok(image.memory);
+            }
+        }
+    }
+
+    let image = read(path)?;
+    let _ = image.save(&cache);
+    Ok(image.memory)
+}
+
+fn cache_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+    name.push(".ici");
+    path.with_file_name(name)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::State;
+
+    #[test]
+    fn round_trips() {
+        let image = Image { memory: vec![1002, 4, 3, 4, 33, -1, i64::MIN, i64::MAX], entry: 4 };
+        let mut bytes = Vec::new();
+        image.write_to(&mut bytes).unwrap();
+        assert!(is_image(&bytes));
+        assert_eq!(bytes[4], 64);
+        assert_eq!(Image::read_from(&bytes[..]).unwrap(), image);
+    }
+
+    #[test]
+    fn is_smaller_than_text() {
+        let image = Image::from_text("3,9,8,9,10,9,4,9,99,-1,8").unwrap();
+        assert_eq!(image.cell_bits(), 8);
+        let mut bytes = Vec::new();
+        image.write_to(&mut bytes).unwrap();
+        // Every cell takes a byte except 99, which is too big once zigzag encoded
+        assert_eq!(bytes.len(), 5 + 2 + 12);
+        assert!(bytes.len() < image.to_text().len());
+        assert_eq!(Image::read_from(&bytes[..]).unwrap().to_text(), "3,9,8,9,10,9,4,9,99,-1,8");
+    }
+
+    #[test]
+    fn measures_cells() {
+        assert_eq!(Image::new(vec![127, -128]).cell_bits(), 8);
+        assert_eq!(Image::new(vec![128]).cell_bits(), 16);
+        assert_eq!(Image::new(vec![i32::MIN as i64]).cell_bits(), 32);
+        assert_eq!(Image::new(vec![i32::MAX as i64 + 1]).cell_bits(), 64);
+        assert_eq!(Image::new(Vec::new()).cell_bits(), 8);
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        assert!(Image::read_from(&b"1,2,3"[..]).is_err());
+        assert!(Image::read_from(&b"ICI\x02\x08\x00\x00"[..]).is_err());
+        assert!(Image::read_from(&b"ICI\x01\x07\x00\x00"[..]).is_err());
+        // 300 doesn't fit the 8 bits the header claims
+        assert!(Image::read_from(&b"ICI\x01\x08\x00\x01\xd8\x04"[..]).is_err());
+        assert!(Image::read_from(&b"ICI\x01\x08\x00\x02\x02"[..]).is_err());
+    }
+
+    #[test]
+    fn resumes_from_a_memory_image() {
+        let mut machine = Machine::new(vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8]);
+        machine.push_input(8);
+        machine.step().unwrap();
+        machine.step().unwrap();
+
+        let mut resumed = Image::of(&machine).machine();
+        assert_eq!(resumed.ip(), 6);
+        assert_eq!(resumed.run().unwrap(), State::Halted);
+        assert_eq!(resumed.take_outputs(), vec![1]);
+    }
+
+    #[test]
+    fn caches_text_programs() {
+        let dir = std::env::temp_dir().join(format!("intcode-image-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let text = dir.join("input.txt");
+        fs::write(&text, "1,0,0,0,99\n").unwrap();
+
+        assert_eq!(read_cached(&text).unwrap(), vec![1, 0, 0, 0, 99]);
+        let cache = dir.join("input.txt.ici");
+        assert_eq!(Image::load(&cache).unwrap().memory, vec![1, 0, 0, 0, 99]);
+        assert_eq!(read(&cache).unwrap(), read(&text).unwrap());
+
+        // The image is used for as long as it's newer than the text
+        Image::new(vec![99]).save(&cache).unwrap();
+        assert_eq!(read_cached(&text).unwrap(), vec![99]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,226 @@
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum Opcode {
+    Add,
+    Multiply,
+    Input,
+    Output,
+    JumpIfTrue,
+    JumpIfFalse,
+    LessThan,
+    Equals,
+    AdjustRelativeBase,
+    Halt,
+}
+
+pub const OPCODES: [Opcode; 10] = [
+    Opcode::Add,
+    Opcode::Multiply,
+    Opcode::Input,
+    Opcode::Output,
+    Opcode::JumpIfTrue,
+    Opcode::JumpIfFalse,
+    Opcode::LessThan,
+    Opcode::Equals,
+    Opcode::AdjustRelativeBase,
+    Opcode::Halt,
+];
+
+impl Opcode {
+    pub fn from_code(code: i64) -> Option<Opcode> {
+        match code {
+            1 => Some(Opcode::Add),
+            2 => Some(Opcode::Multiply),
+            3 => Some(Opcode::Input),
+            4 => Some(Opcode::Output),
+            5 => Some(Opcode::JumpIfTrue),
+            6 => Some(Opcode::JumpIfFalse),
+            7 => Some(Opcode::LessThan),
+            8 => Some(Opcode::Equals),
+            9 => Some(Opcode::AdjustRelativeBase),
+            99 => Some(Opcode::Halt),
+            _ => None,
+        }
+    }
+
+    pub fn code(self) -> i64 {
+        match self {
+            Opcode::Add => 1,
+            Opcode::Multiply => 2,
+            Opcode::Input => 3,
+            Opcode::Output => 4,
+            Opcode::JumpIfTrue => 5,
+            Opcode::JumpIfFalse => 6,
+            Opcode::LessThan => 7,
+            Opcode::Equals => 8,
+            Opcode::AdjustRelativeBase => 9,
+            Opcode::Halt => 99,
+        }
+    }
+
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Opcode> {
+        OPCODES.iter().copied().find(|op| op.mnemonic() == mnemonic)
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Opcode::Add => "add",
+            Opcode::Multiply => "mul",
+            Opcode::Input => "in",
+            Opcode::Output => "out",
+            Opcode::JumpIfTrue => "jt",
+            Opcode::JumpIfFalse => "jf",
+            Opcode::LessThan => "lt",
+            Opcode::Equals => "eq",
+            Opcode::AdjustRelativeBase => "arb",
+            Opcode::Halt => "hlt",
+        }
+    }
+
+    pub fn parameter_count(self) -> usize {
+        match self {
+            Opcode::Add | Opcode::Multiply | Opcode::LessThan | Opcode::Equals => 3,
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => 2,
+            Opcode::Input | Opcode::Output | Opcode::AdjustRelativeBase => 1,
+            Opcode::Halt => 0,
+        }
+    }
+
+    // The last parameter of these instructions is an address that gets written to
+    pub fn writes_memory(self) -> bool {
+        matches!(self, Opcode::Add | Opcode::Multiply | Opcode::LessThan | Opcode::Equals | Opcode::Input)
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.mnemonic())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Mode {
+    Position,
+    Immediate,
+    Relative,
+}
+
+impl Mode {
+    pub fn from_digit(digit: i64) -> Option<Mode> {
+        match digit {
+            0 => Some(Mode::Position),
+            1 => Some(Mode::Immediate),
+            2 => Some(Mode::Relative),
+            _ => None,
+        }
+    }
+
+    pub fn digit(self) -> i64 {
+        match self {
+            Mode::Position => 0,
+            Mode::Immediate => 1,
+            Mode::Relative => 2,
+        }
+    }
+
+    // Prefix used for the mode in assembly source
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Mode::Position => "",
+            Mode::Immediate => "#",
+            Mode::Relative => "@",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct Instruction {
+    pub opcode: Opcode,
+    pub modes: [Mode; 3],
+}
+
+impl Instruction {
+    pub fn new(opcode: Opcode, modes: [Mode; 3]) -> Instruction {
+        Instruction { opcode, modes }
+    }
+
+    // Only accepts words that encode back to themselves, so mode digits for parameters
+    // the opcode doesn't have, and immediate mode on written parameters, are rejected.
+    pub fn decode(word: i64) -> Option<Instruction> {
+        if word < 0 {
+            return None;
+        }
+
+        let opcode = Opcode::from_code(word % 100)?;
+        let count = opcode.parameter_count();
+
+        let mut modes = [Mode::Position; 3];
+        let mut rest = word / 100;
+        for mode in modes.iter_mut().take(count) {
+            *mode = Mode::from_digit(rest % 10)?;
+            rest /= 10;
+        }
+
+        if rest != 0 {
+            return None;
+        }
+
+        if opcode.writes_memory() && modes[count - 1] == Mode::Immediate {
+            return None;
+        }
+
+        Some(Instruction { opcode, modes })
+    }
+
+    pub fn encode(&self) -> i64 {
+        self.modes[..self.opcode.parameter_count()]
+            .iter()
+            .rev()
+            .fold(0, |acc, mode| acc * 10 + mode.digit())
+            * 100
+            + self.opcode.code()
+    }
+
+    pub fn length(&self) -> usize {
+        self.opcode.parameter_count() + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_modes() {
+        let instruction = Instruction::decode(1002).unwrap();
+        assert_eq!(instruction.opcode, Opcode::Multiply);
+        assert_eq!(instruction.modes, [Mode::Position, Mode::Immediate, Mode::Position]);
+    }
+
+    #[test]
+    fn decodes_relative_mode() {
+        let instruction = Instruction::decode(204).unwrap();
+        assert_eq!(instruction.opcode, Opcode::Output);
+        assert_eq!(instruction.modes[0], Mode::Relative);
+    }
+
+    #[test]
+    fn rejects_invalid_words() {
+        assert_eq!(Instruction::decode(42), None);
+        assert_eq!(Instruction::decode(-1), None);
+        assert_eq!(Instruction::decode(301), None);
+        assert_eq!(Instruction::decode(10101), None);
+        assert_eq!(Instruction::decode(103), None);
+        assert_eq!(Instruction::decode(199), None);
+    }
+
+    #[test]
+    fn encodes_every_valid_word_back() {
+        for word in 0..30_000 {
+            if let Some(instruction) = Instruction::decode(word) {
+                assert_eq!(instruction.encode(), word);
+            }
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use std::num::ParseIntError;
+
+pub mod asm;
+pub mod cfg;
+pub mod compile;
+pub mod debugger;
+pub mod disasm;
+pub mod fuzz;
+pub mod image;
+mod instruction;
+pub mod machine;
+pub mod network;
+pub mod profile;
+pub mod search;
+pub mod symbolic;
+pub mod trace;
+mod varint;
+
+pub use instruction::{Instruction, Mode, Opcode, OPCODES};
+pub use machine::{Limits, Machine, State};
+
+// Parses the comma separated format the puzzle inputs are distributed in
+pub fn parse_program(input: &str) -> Result<Vec<i64>, ParseIntError> {
+    input.trim().split(',').map(|n| n.trim().parse()).collect()
+}
+
+pub fn format_program(program: &[i64]) -> String {
+    program.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_formats_programs() {
+        let program = parse_program("1002,4,3,4,33\n").unwrap();
+        assert_eq!(program, vec![1002, 4, 3, 4, 33]);
+        assert_eq!(format_program(&program), "1002,4,3,4,33");
+    }
+}
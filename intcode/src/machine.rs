@@ -0,0 +1,456 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{Instruction, Mode, Opcode};
+
+mod compiled;
+mod history;
+mod snapshot;
+
+pub use compiled::{in_code, Compiled, Cpu, Exit};
+pub use snapshot::Snapshot;
+use history::Undo;
+
+// Addresses above this are treated as a bug in the program instead of growing memory forever
+pub const MAX_MEMORY: usize = 1 << 20;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+    InvalidInstruction { address: usize, word: i64 },
+    InvalidAddress { address: usize, target: i64 },
+    BudgetExceeded { address: usize, steps: u64 },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidInstruction { address, word } => write!(f, "invalid instruction {} at {}", word, address),
+            Error::InvalidAddress { address, target } => write!(f, "invalid address {} used at {}", target, address),
+            Error::BudgetExceeded { address, steps } => write!(f, "budget exceeded at {} after {} steps", address, steps),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// How long a run may take before it's stopped with `Error::BudgetExceeded`. The step limit
+// counts every instruction the machine has executed, not only the ones in the current run.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Limits {
+    pub max_steps: Option<u64>,
+    pub timeout: Option<Duration>,
+}
+
+// Checking the clock is slow compared to executing an instruction, so only do it this often
+const STEPS_BETWEEN_CLOCK_CHECKS: u64 = 1024;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum State {
+    Running,
+    AwaitingInput,
+    Halted,
+}
+
+// A decoded parameter. `address` is the cell it refers to in position and relative mode, and
+// `value` is what was read from it, or for the parameter an instruction writes to, what was written.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Operand {
+    pub mode: Mode,
+    pub raw: i64,
+    pub address: Option<usize>,
+    pub value: i64,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Write {
+    pub address: usize,
+    pub old: i64,
+    pub new: i64,
+}
+
+// Everything an executed instruction did, registers are the values from before it ran
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Step {
+    pub address: usize,
+    pub relative_base: i64,
+    pub instruction: Instruction,
+    operands: [Operand; 3],
+    pub write: Option<Write>,
+    pub input: Option<i64>,
+    pub output: Option<i64>,
+    pub next: usize,
+}
+
+impl Step {
+    pub fn operands(&self) -> &[Operand] {
+        &self.operands[..self.instruction.opcode.parameter_count()]
+    }
+}
+
+// Clones share memory with each other until one of them writes to it, so forking a machine
+// is cheap even for big programs
+#[derive(Clone, Debug)]
+pub struct Machine {
+    memory: Arc<Vec<i64>>,
+    // Instructions already decoded at each address, cleared when the cell is written
+    decoded: Arc<Vec<Option<Instruction>>>,
+    ip: usize,
+    relative_base: i64,
+    halted: bool,
+    steps: u64,
+    inputs: VecDeque<i64>,
+    outputs: VecDeque<i64>,
+    // Undo log of executed instructions, only kept once `record_history` is called
+    history: Option<Vec<Undo>>,
+}
+
+impl Machine {
+    pub fn new(program: Vec<i64>) -> Machine {
+        Machine {
+            decoded: Arc::new(vec![None; program.len()]),
+            memory: Arc::new(program),
+            ip: 0,
+            relative_base: 0,
+            halted: false,
+            steps: 0,
+            inputs: VecDeque::new(),
+            outputs: VecDeque::new(),
+            history: None,
+        }
+    }
+
+    // Starts at `entry` instead of address 0, like a saved memory image
+    pub fn starting_at(program: Vec<i64>, entry: usize) -> Machine {
+        Machine { ip: entry, ..Machine::new(program) }
+    }
+
+    pub fn memory(&self) -> &[i64] {
+        &self.memory
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn relative_base(&self) -> i64 {
+        self.relative_base
+    }
+
+    // Number of instructions executed so far
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn read(&self, address: usize) -> i64 {
+        self.memory.get(address).copied().unwrap_or(0)
+    }
+
+    pub fn write(&mut self, address: usize, value: i64) {
+        let memory = Arc::make_mut(&mut self.memory);
+        let decoded = Arc::make_mut(&mut self.decoded);
+        if address >= memory.len() {
+            memory.resize(address + 1, 0);
+            decoded.resize(address + 1, None);
+        }
+        memory[address] = value;
+        decoded[address] = None;
+    }
+
+    pub fn push_input(&mut self, value: i64) {
+        self.inputs.push_back(value)
+    }
+
+    pub fn extend_inputs<I: IntoIterator<Item = i64>>(&mut self, values: I) {
+        self.inputs.extend(values)
+    }
+
+    pub fn pending_inputs(&self) -> &VecDeque<i64> {
+        &self.inputs
+    }
+
+    pub fn outputs(&self) -> &VecDeque<i64> {
+        &self.outputs
+    }
+
+    pub fn pop_output(&mut self) -> Option<i64> {
+        self.outputs.pop_front()
+    }
+
+    pub fn take_outputs(&mut self) -> Vec<i64> {
+        self.outputs.drain(..).collect()
+    }
+
+    pub fn state(&self) -> State {
+        if self.halted {
+            State::Halted
+        } else if self.inputs.is_empty() && self.read(self.ip) % 100 == Opcode::Input.code() {
+            State::AwaitingInput
+        } else {
+            State::Running
+        }
+    }
+
+    // Runs until the program halts or needs input that hasn't been given yet
+    pub fn run(&mut self) -> Result<State, Error> {
+        self.run_with(|_| {})
+    }
+
+    // Same as `run`, but calls `on_step` after every executed instruction
+    pub fn run_with<F: FnMut(&Step)>(&mut self, mut on_step: F) -> Result<State, Error> {
+        while let Some(step) = self.step()? {
+            on_step(&step);
+        }
+        Ok(self.state())
+    }
+
+    // Same as `run`, but gives up once the limits are reached. The machine is left as it was
+    // before the instruction that would have gone over, so it can be resumed.
+    pub fn run_limited(&mut self, limits: Limits) -> Result<State, Error> {
+        self.run_limited_with(limits, |_| {})
+    }
+
+    pub fn run_limited_with<F: FnMut(&Step)>(&mut self, limits: Limits, mut on_step: F) -> Result<State, Error> {
+        let deadline = limits.timeout.map(|timeout| Instant::now() + timeout);
+        let mut executed = 0;
+
+        loop {
+            let out_of_steps = limits.max_steps.is_some_and(|max| self.steps >= max);
+            let out_of_time = executed % STEPS_BETWEEN_CLOCK_CHECKS == 0 && deadline.is_some_and(|d| Instant::now() >= d);
+            if (out_of_steps || out_of_time) && self.state() == State::Running {
+                return Err(Error::BudgetExceeded { address: self.ip, steps: self.steps });
+            }
+
+            match self.step()? {
+                Some(step) => on_step(&step),
+                None => return Ok(self.state()),
+            }
+            executed += 1;
+        }
+    }
+
+    // Executes a single instruction. Nothing happens, and None is returned, if the machine has
+    // halted or is waiting for input.
+    pub fn step(&mut self) -> Result<Option<Step>, Error> {
+        if self.halted {
+            return Ok(None);
+        }
+
+        let address = self.ip;
+        let instruction = self.decode(address)?;
+        let opcode = instruction.opcode;
+
+        if opcode == Opcode::Input && self.inputs.is_empty() {
+            return Ok(None);
+        }
+
+        let count = opcode.parameter_count();
+        let mut operands = [Operand { mode: Mode::Immediate, raw: 0, address: None, value: 0 }; 3];
+        for (index, operand) in operands.iter_mut().enumerate().take(count) {
+            let mode = instruction.modes[index];
+            let raw = self.read(address + 1 + index);
+            let target = match mode {
+                Mode::Position => Some(self.check_address(address, raw)?),
+                Mode::Relative => Some(self.check_address(address, self.relative_base.wrapping_add(raw))?),
+                Mode::Immediate => None,
+            };
+            let is_written = opcode.writes_memory() && index == count - 1;
+            let value = match target {
+                Some(target) if !is_written => self.read(target),
+                _ => raw,
+            };
+            *operand = Operand { mode, raw, address: target, value };
+        }
+
+        let relative_base = self.relative_base;
+        let first = operands[0].value;
+        let second = operands[1].value;
+        let mut next = address + instruction.length();
+        let mut result = None;
+        let mut input = None;
+        let mut output = None;
+
+        match opcode {
+            Opcode::Add => result = Some(first.wrapping_add(second)),
+            Opcode::Multiply => result = Some(first.wrapping_mul(second)),
+            Opcode::LessThan => result = Some((first < second) as i64),
+            Opcode::Equals => result = Some((first == second) as i64),
+            Opcode::Input => {
+                input = self.inputs.pop_front();
+                result = input;
+            }
+            Opcode::Output => {
+                output = Some(first);
+                self.outputs.push_back(first);
+            }
+            Opcode::JumpIfTrue => {
+                if first != 0 {
+                    next = self.check_address(address, second)?;
+                }
+            }
+            Opcode::JumpIfFalse => {
+                if first == 0 {
+                    next = self.check_address(address, second)?;
+                }
+            }
+            Opcode::AdjustRelativeBase => self.relative_base = relative_base.wrapping_add(first),
+            Opcode::Halt => {
+                self.halted = true;
+                next = address;
+            }
+        }
+
+        let memory_len = self.memory.len();
+        let write = result.map(|new| {
+            let operand = &mut operands[count - 1];
+            let target = operand.address.unwrap();
+            operand.value = new;
+            let old = self.read(target);
+            self.write(target, new);
+            Write { address: target, old, new }
+        });
+
+        if let Some(history) = &mut self.history {
+            history.push(Undo { address, relative_base, write, memory_len, input, output: output.is_some() });
+        }
+
+        self.ip = next;
+        self.steps += 1;
+
+        Ok(Some(Step { address, relative_base, instruction, operands, write, input, output, next }))
+    }
+
+    fn decode(&mut self, address: usize) -> Result<Instruction, Error> {
+        if let Some(Some(instruction)) = self.decoded.get(address) {
+            return Ok(*instruction);
+        }
+
+        let word = self.read(address);
+        let instruction = Instruction::decode(word).ok_or(Error::InvalidInstruction { address, word })?;
+        if address < self.decoded.len() {
+            Arc::make_mut(&mut self.decoded)[address] = Some(instruction);
+        }
+        Ok(instruction)
+    }
+
+    fn check_address(&self, address: usize, target: i64) -> Result<usize, Error> {
+        if target < 0 || target as usize >= MAX_MEMORY {
+            Err(Error::InvalidAddress { address, target })
+        } else {
+            Ok(target as usize)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_with_input(program: Vec<i64>, input: i64) -> Vec<i64> {
+        let mut machine = Machine::new(program);
+        machine.push_input(input);
+        assert_eq!(machine.run().unwrap(), State::Halted);
+        machine.take_outputs()
+    }
+
+    #[test]
+    fn runs_day2_examples() {
+        let mut machine = Machine::new(vec![1, 1, 1, 4, 99, 5, 6, 0, 99]);
+        machine.run().unwrap();
+        assert_eq!(machine.memory(), &[30, 1, 1, 4, 2, 5, 6, 0, 99]);
+    }
+
+    #[test]
+    fn runs_day5_examples() {
+        let program = vec![3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0, 0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4, 20, 1105, 1, 46, 98, 99];
+        assert_eq!(run_with_input(program.clone(), 7), vec![999]);
+        assert_eq!(run_with_input(program.clone(), 8), vec![1000]);
+        assert_eq!(run_with_input(program, 9), vec![1001]);
+    }
+
+    #[test]
+    fn supports_relative_mode_and_growing_memory() {
+        let quine = vec![109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99];
+        let mut machine = Machine::new(quine.clone());
+        machine.run().unwrap();
+        assert_eq!(machine.take_outputs(), quine);
+    }
+
+    #[test]
+    fn waits_for_input() {
+        let mut machine = Machine::new(vec![3, 5, 4, 5, 99, 0]);
+        assert_eq!(machine.run().unwrap(), State::AwaitingInput);
+        assert_eq!(machine.ip(), 0);
+
+        machine.push_input(42);
+        assert_eq!(machine.run().unwrap(), State::Halted);
+        assert_eq!(machine.take_outputs(), vec![42]);
+    }
+
+    #[test]
+    fn step_describes_effects() {
+        let mut machine = Machine::new(vec![1002, 4, 3, 4, 33]);
+        let step = machine.step().unwrap().unwrap();
+        assert_eq!(step.address, 0);
+        assert_eq!(step.operands()[0].value, 33);
+        assert_eq!(step.operands()[1].value, 3);
+        assert_eq!(step.write, Some(Write { address: 4, old: 33, new: 99 }));
+        assert_eq!(step.next, 4);
+        assert_eq!(machine.run().unwrap(), State::Halted);
+    }
+
+    #[test]
+    fn decodes_again_after_self_modification() {
+        // The first add turns the instruction at 8 from a multiply into an add before it runs
+        let program = vec![1101, 1100, 1, 8, 1105, 1, 8, 99, 1102, 3, 4, 0, 99];
+        let mut machine = Machine::new(program);
+        machine.decode(8).unwrap();
+
+        machine.run().unwrap();
+        assert_eq!(machine.read(0), 7);
+    }
+
+    fn infinite_loop() -> Vec<i64> {
+        vec![1105, 1, 0]
+    }
+
+    #[test]
+    fn stops_when_out_of_steps() {
+        let mut machine = Machine::new(infinite_loop());
+        let limits = Limits { max_steps: Some(1000), ..Limits::default() };
+        assert_eq!(machine.run_limited(limits), Err(Error::BudgetExceeded { address: 0, steps: 1000 }));
+        assert_eq!(machine.steps(), 1000);
+
+        let limits = Limits { max_steps: Some(1500), ..Limits::default() };
+        assert_eq!(machine.run_limited(limits), Err(Error::BudgetExceeded { address: 0, steps: 1500 }));
+    }
+
+    #[test]
+    fn stops_when_out_of_time() {
+        let mut machine = Machine::new(infinite_loop());
+        let limits = Limits { timeout: Some(Duration::from_millis(20)), ..Limits::default() };
+        match machine.run_limited(limits) {
+            Err(Error::BudgetExceeded { address: 0, steps }) => assert!(steps > 0),
+            other => panic!("Expected the time budget to run out, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn finishing_within_limits_is_not_an_error() {
+        let mut machine = Machine::new(vec![1101, 1, 2, 0, 99]);
+        let limits = Limits { max_steps: Some(2), timeout: Some(Duration::from_secs(10)) };
+        assert_eq!(machine.run_limited(limits), Ok(State::Halted));
+        assert_eq!(machine.steps(), 2);
+
+        let mut machine = Machine::new(vec![3, 0, 99]);
+        assert_eq!(machine.run_limited(Limits { max_steps: Some(0), ..Limits::default() }), Ok(State::AwaitingInput));
+    }
+
+    #[test]
+    fn reports_errors() {
+        assert_eq!(Machine::new(vec![42]).run(), Err(Error::InvalidInstruction { address: 0, word: 42 }));
+        assert_eq!(Machine::new(vec![1, -1, 0, 0]).run(), Err(Error::InvalidAddress { address: 0, target: -1 }));
+        assert_eq!(Machine::new(vec![1105, 1, -7]).run(), Err(Error::InvalidAddress { address: 0, target: -7 }));
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use super::{Error, Machine, State, MAX_MEMORY};
+
+// What compiled code works on. It gets direct access to the parts of the machine it changes,
+// memory is only reachable through methods so addresses are always checked.
+pub struct Cpu<'a> {
+    memory: &'a mut Vec<i64>,
+    pub relative_base: i64,
+    pub inputs: &'a mut VecDeque<i64>,
+    pub outputs: &'a mut VecDeque<i64>,
+    pub steps: u64,
+}
+
+impl Cpu<'_> {
+    // Checks an address used by the instruction at `at`
+    pub fn address(&self, at: usize, target: i64) -> Result<usize, Error> {
+        if target < 0 || target as usize >= MAX_MEMORY {
+            Err(Error::InvalidAddress { address: at, target })
+        } else {
+            Ok(target as usize)
+        }
+    }
+
+    pub fn load(&self, at: usize, target: i64) -> Result<i64, Error> {
+        let address = self.address(at, target)?;
+        Ok(self.memory.get(address).copied().unwrap_or(0))
+    }
+
+    pub fn store(&mut self, address: usize, value: i64) {
+        if address >= self.memory.len() {
+            self.memory.resize(address + 1, 0);
+        }
+        self.memory[address] = value;
+    }
+
+    // Whether the cells the code was compiled from still hold the same values
+    pub fn code_unchanged(&self, program: &[i64], code: &[(usize, usize)]) -> bool {
+        code.iter().all(|&(start, end)| self.memory.get(start..end) == program.get(start..end))
+    }
+}
+
+// Whether an address is inside one of the sorted, non-overlapping ranges of compiled code
+pub fn in_code(code: &[(usize, usize)], address: usize) -> bool {
+    match code.binary_search_by(|&(start, _)| start.cmp(&address)) {
+        Ok(_) => true,
+        Err(0) => false,
+        Err(index) => address < code[index - 1].1,
+    }
+}
+
+// How compiled code stopped. It hands over to the interpreter for anything it can't handle,
+// like running out of input, jumping to code it doesn't know or writing to its own code.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Exit {
+    Halted { at: usize },
+    Interpret { at: usize },
+}
+
+// The `run` function of a program compiled with `compile::to_rust`, it's started at the
+// current instruction pointer
+pub type Compiled = fn(&mut Cpu, usize) -> Result<Exit, Error>;
+
+impl Machine {
+    // Same as `run`, but executes as much as possible with compiled code. The code must have
+    // been compiled from this program, if its instructions have since changed it falls back
+    // to the interpreter for everything. So does recording history, which compiled code can't.
+    pub fn run_compiled(&mut self, code: Compiled) -> Result<State, Error> {
+        if self.halted || self.is_recording_history() {
+            return self.run();
+        }
+
+        let mut cpu = Cpu {
+            memory: Arc::make_mut(&mut self.memory),
+            relative_base: self.relative_base,
+            inputs: &mut self.inputs,
+            outputs: &mut self.outputs,
+            steps: self.steps,
+        };
+        let result = code(&mut cpu, self.ip);
+        self.relative_base = cpu.relative_base;
+        self.steps = cpu.steps;
+
+        // The compiled code doesn't keep the decode cache up to date
+        self.decoded = Arc::new(vec![None; self.memory.len()]);
+
+        match result {
+            Ok(Exit::Halted { at }) => {
+                self.ip = at;
+                self.halted = true;
+                Ok(State::Halted)
+            }
+            Ok(Exit::Interpret { at }) => {
+                self.ip = at;
+                self.run()
+            }
+            Err(e) => {
+                self.ip = match e {
+                    Error::InvalidInstruction { address, .. } | Error::InvalidAddress { address, .. } | Error::BudgetExceeded { address, .. } => address,
+                };
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_code_addresses() {
+        let code = [(0, 4), (6, 7), (10, 20)];
+        let inside: Vec<usize> = (0..25).filter(|&address| in_code(&code, address)).collect();
+        assert_eq!(inside, vec![0, 1, 2, 3, 6, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19]);
+        assert!(!in_code(&[], 0));
+    }
+}
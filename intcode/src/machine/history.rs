@@ -0,0 +1,168 @@
+use std::sync::Arc;
+
+use super::{Machine, Write};
+
+// What is needed to put the machine back to how it was before an instruction ran
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(super) struct Undo {
+    pub address: usize,
+    pub relative_base: i64,
+    pub write: Option<Write>,
+    // Writes past the end grow memory, this is the length from before
+    pub memory_len: usize,
+    pub input: Option<i64>,
+    pub output: bool,
+}
+
+// Stepping backwards only undoes executed instructions. Changes made through `write`,
+// `push_input` or taking outputs aren't recorded, so rewinding past them leaves them in place.
+impl Machine {
+    // Starts keeping an undo log, the machine can be rewound to any step from here on
+    pub fn record_history(&mut self) {
+        if self.history.is_none() {
+            self.history = Some(Vec::new());
+        }
+    }
+
+    pub fn is_recording_history(&self) -> bool {
+        self.history.is_some()
+    }
+
+    // The earliest step the machine can be rewound to
+    pub fn history_start(&self) -> Option<u64> {
+        self.history.as_ref().map(|history| self.steps - history.len() as u64)
+    }
+
+    // Undoes the last executed instruction, returning its address
+    pub fn step_back(&mut self) -> Option<usize> {
+        let undo = self.history.as_mut()?.pop()?;
+
+        if let Some(write) = undo.write {
+            self.write(write.address, write.old);
+        }
+        if self.memory.len() > undo.memory_len {
+            Arc::make_mut(&mut self.memory).truncate(undo.memory_len);
+            Arc::make_mut(&mut self.decoded).truncate(undo.memory_len);
+        }
+        if let Some(input) = undo.input {
+            self.inputs.push_front(input);
+        }
+        if undo.output {
+            self.outputs.pop_back();
+        }
+
+        self.ip = undo.address;
+        self.relative_base = undo.relative_base;
+        self.halted = false;
+        self.steps -= 1;
+        Some(undo.address)
+    }
+
+    // Rewinds until `steps` instructions have been executed. Nothing happens if that is before
+    // the history starts or hasn't happened yet.
+    pub fn rewind_to(&mut self, steps: u64) -> bool {
+        if self.history_start().is_none_or(|start| steps < start || steps > self.steps) {
+            return false;
+        }
+        while self.steps > steps {
+            self.step_back();
+        }
+        true
+    }
+
+    // Rewinds to just before the instruction that last wrote the address and returns the write.
+    // The machine is left unchanged if no recorded instruction wrote to it.
+    pub fn rewind_to_write(&mut self, address: usize) -> Option<Write> {
+        let history = self.history.as_ref()?;
+        let index = history.iter().rposition(|undo| undo.write.is_some_and(|w| w.address == address))?;
+        let write = history[index].write;
+
+        while self.history.as_ref().is_some_and(|history| history.len() > index) {
+            self.step_back();
+        }
+        write
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Limits, State};
+
+    fn comparison_program() -> Vec<i64> {
+        vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8]
+    }
+
+    #[test]
+    fn steps_back_to_the_start() {
+        let mut machine = Machine::new(comparison_program());
+        machine.record_history();
+        machine.push_input(8);
+        assert_eq!(machine.run().unwrap(), State::Halted);
+        assert_eq!(machine.outputs(), &[1]);
+
+        assert_eq!(machine.step_back(), Some(8));
+        assert_eq!(machine.state(), State::Running);
+        assert_eq!(machine.step_back(), Some(6));
+        assert!(machine.outputs().is_empty());
+        assert_eq!(machine.step_back(), Some(2));
+        assert_eq!(machine.step_back(), Some(0));
+        assert_eq!(machine.step_back(), None);
+
+        assert_eq!(machine.memory(), &comparison_program()[..]);
+        assert_eq!(machine.pending_inputs(), &[8]);
+        assert_eq!(machine.steps(), 0);
+
+        machine.run().unwrap();
+        assert_eq!(machine.take_outputs(), vec![1]);
+    }
+
+    #[test]
+    fn undoes_registers_and_growing_memory() {
+        let quine = vec![109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99];
+        let mut machine = Machine::new(quine.clone());
+        machine.record_history();
+        let original = machine.snapshot();
+
+        machine.run().unwrap();
+        assert_eq!(machine.take_outputs(), quine);
+        assert!(machine.rewind_to(0));
+        assert_eq!(machine.snapshot(), original);
+    }
+
+    #[test]
+    fn rewinds_to_steps_and_writes() {
+        let mut machine = Machine::new(comparison_program());
+        machine.push_input(8);
+        machine.step().unwrap();
+        machine.record_history();
+        machine.run().unwrap();
+
+        assert_eq!(machine.history_start(), Some(1));
+        assert!(!machine.rewind_to(0));
+        assert_eq!(machine.steps(), 4);
+
+        assert_eq!(machine.rewind_to_write(9), Some(Write { address: 9, old: 8, new: 1 }));
+        assert_eq!((machine.ip(), machine.steps(), machine.read(9)), (2, 1, 8));
+
+        // The input that set it to 8 ran before the history was recorded
+        assert_eq!(machine.rewind_to_write(9), None);
+        assert_eq!(machine.steps(), 1);
+
+        assert!(machine.rewind_to(1));
+        let limits = Limits { max_steps: Some(3), ..Limits::default() };
+        assert!(machine.run_limited(limits).is_err());
+        assert_eq!((machine.ip(), machine.steps()), (8, 3));
+        assert!(!machine.rewind_to(4));
+    }
+
+    #[test]
+    fn does_nothing_without_history() {
+        let mut machine = Machine::new(comparison_program());
+        machine.push_input(8);
+        machine.run().unwrap();
+        assert_eq!(machine.step_back(), None);
+        assert_eq!(machine.history_start(), None);
+        assert!(!machine.rewind_to(0));
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use super::Machine;
+use crate::varint;
+
+const MAGIC: &[u8; 4] = b"ICS\x01";
+
+// The complete state of a machine. Taking one only copies registers and pending I/O, memory
+// is shared with the machine until either side writes to it. The undo history is left out, so a
+// machine that has been recording for a long time is as cheap to snapshot as any other.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    machine: Machine,
+}
+
+impl Machine {
+    pub fn snapshot(&self) -> Snapshot {
+        let machine = Machine {
+            memory: Arc::clone(&self.memory),
+            decoded: Arc::clone(&self.decoded),
+            ip: self.ip,
+            relative_base: self.relative_base,
+            halted: self.halted,
+            steps: self.steps,
+            inputs: self.inputs.clone(),
+            outputs: self.outputs.clone(),
+            history: None,
+        };
+        Snapshot { machine }
+    }
+
+    // A recording machine keeps recording, from the restored step on
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        let recording = self.is_recording_history();
+        *self = snapshot.machine.clone();
+        if recording {
+            self.record_history();
+        }
+    }
+}
+
+impl Snapshot {
+    // A new machine continuing from the snapshot
+    pub fn fork(&self) -> Machine {
+        self.machine.clone()
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let m = &self.machine;
+        writer.write_all(MAGIC)?;
+        varint::write_u64(&mut writer, m.ip as u64)?;
+        varint::write_i64(&mut writer, m.relative_base)?;
+        varint::write_u64(&mut writer, m.steps)?;
+        writer.write_all(&[m.halted as u8])?;
+        write_cells(&mut writer, m.inputs.iter())?;
+        write_cells(&mut writer, m.outputs.iter())?;
+        write_cells(&mut writer, m.memory.iter())?;
+        writer.flush()
+    }
+
+    pub fn read_from<R: Read>(mut reader: R) -> io::Result<Snapshot> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a snapshot"));
+        }
+
+        let ip = varint::expect_u64(&mut reader)? as usize;
+        let relative_base = varint::expect_i64(&mut reader)?;
+        let steps = varint::expect_u64(&mut reader)?;
+        let mut halted = [0u8];
+        reader.read_exact(&mut halted)?;
+        let inputs: VecDeque<i64> = read_cells(&mut reader)?.into();
+        let outputs: VecDeque<i64> = read_cells(&mut reader)?.into();
+        let memory = read_cells(&mut reader)?;
+
+        let mut machine = Machine::new(memory);
+        machine.ip = ip;
+        machine.relative_base = relative_base;
+        machine.steps = steps;
+        machine.halted = halted[0] != 0;
+        machine.inputs = inputs;
+        machine.outputs = outputs;
+        Ok(Snapshot { machine })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Snapshot> {
+        Snapshot::read_from(BufReader::new(File::open(path)?))
+    }
+}
+
+// The decode cache doesn't count, it only depends on memory
+impl PartialEq for Snapshot {
+    fn eq(&self, other: &Snapshot) -> bool {
+        let (a, b) = (&self.machine, &other.machine);
+        (Arc::ptr_eq(&a.memory, &b.memory) || a.memory == b.memory)
+            && a.ip == b.ip
+            && a.relative_base == b.relative_base
+            && a.steps == b.steps
+            && a.halted == b.halted
+            && a.inputs == b.inputs
+            && a.outputs == b.outputs
+    }
+}
+
+impl Eq for Snapshot {}
+
+fn write_cells<'a, W: Write, I: ExactSizeIterator<Item = &'a i64>>(writer: &mut W, cells: I) -> io::Result<()> {
+    varint::write_u64(writer, cells.len() as u64)?;
+    for cell in cells {
+        varint::write_i64(writer, *cell)?;
+    }
+    Ok(())
+}
+
+fn read_cells<R: Read>(reader: &mut R) -> io::Result<Vec<i64>> {
+    let count = varint::expect_u64(reader)? as usize;
+    if count > super::MAX_MEMORY {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "snapshot is too big"));
+    }
+    (0..count).map(|_| varint::expect_i64(reader)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::State;
+
+    // Outputs 999, 1000 or 1001 depending on whether the input is below, equal to or above 8
+    fn large_program() -> Vec<i64> {
+        vec![3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0, 0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4, 20, 1105, 1, 46, 98, 99]
+    }
+
+    #[test]
+    fn forks_continue_independently() {
+        let mut machine = Machine::new(large_program());
+        assert_eq!(machine.run().unwrap(), State::AwaitingInput);
+        let snapshot = machine.snapshot();
+
+        let results: Vec<Vec<i64>> = (7..=9)
+            .map(|input| {
+                let mut fork = snapshot.fork();
+                fork.push_input(input);
+                fork.run().unwrap();
+                fork.take_outputs()
+            })
+            .collect();
+
+        assert_eq!(results, vec![vec![999], vec![1000], vec![1001]]);
+        assert_eq!(snapshot.fork().memory(), &large_program()[..]);
+    }
+
+    #[test]
+    fn restores_state() {
+        let mut machine = Machine::new(large_program());
+        machine.run().unwrap();
+        let snapshot = machine.snapshot();
+
+        machine.push_input(7);
+        machine.run().unwrap();
+        assert_ne!(machine.snapshot(), snapshot);
+
+        machine.restore(&snapshot);
+        assert_eq!(machine.snapshot(), snapshot);
+        machine.push_input(8);
+        machine.run().unwrap();
+        assert_eq!(machine.take_outputs(), vec![1000]);
+    }
+
+    #[test]
+    fn shares_memory_until_written() {
+        let machine = Machine::new(large_program());
+        let snapshot = machine.snapshot();
+        let mut fork = snapshot.fork();
+        assert!(Arc::ptr_eq(&machine.memory, &fork.memory));
+
+        fork.write(0, 4);
+        assert!(!Arc::ptr_eq(&machine.memory, &fork.memory));
+        assert_eq!(machine.read(0), 3);
+    }
+
+    #[test]
+    fn leaves_out_the_history() {
+        let mut machine = Machine::new(large_program());
+        machine.record_history();
+        machine.push_input(7);
+        machine.run().unwrap();
+        let snapshot = machine.snapshot();
+        assert!(snapshot.machine.history.is_none());
+        assert!(!snapshot.fork().is_recording_history());
+
+        machine.restore(&snapshot);
+        assert_eq!(machine.history_start(), Some(machine.steps()));
+        assert!(machine.step_back().is_none());
+    }
+
+    #[test]
+    fn serializes_snapshots() {
+        let mut machine = Machine::new(large_program());
+        machine.extend_inputs(vec![7, -3]);
+        machine.step().unwrap();
+        machine.write(60, -12);
+
+        let snapshot = machine.snapshot();
+        let mut bytes = Vec::new();
+        snapshot.write_to(&mut bytes).unwrap();
+
+        let loaded = Snapshot::read_from(&bytes[..]).unwrap();
+        assert_eq!(loaded, snapshot);
+
+        let mut resumed = loaded.fork();
+        resumed.run().unwrap();
+        assert_eq!(resumed.take_outputs(), vec![999]);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(Snapshot::read_from(&b"3,9,8,9"[..]).is_err());
+        assert!(Snapshot::read_from(&MAGIC[..]).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::ops::Range;
+use std::time::Duration;
+use std::{env, fs, process};
+
+use intcode::cfg::Graph;
+use intcode::debugger::Debugger;
+use intcode::image::{self, Image};
+use intcode::profile::Profile;
+use intcode::trace::{Format, Tracer};
+use intcode::{asm, compile, disasm, format_program, Limits, Machine, Opcode, State};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["asm", path] => {
+            let source = read_file(path);
+            match asm::assemble(&source) {
+                Ok(program) => println!("{}", format_program(&program)),
+                Err(e) => fail(&format!("{}: {}", path, e)),
+            }
+        }
+        ["disasm", path] => print!("{}", disasm::to_source(&read_program(path))),
+        ["cfg", path] => {
+            let program = read_program(path);
+            print!("{}", Graph::build(&program).to_dot(&program));
+        }
+        ["compile", path] => print!("{}", compile::to_rust(&read_program(path))),
+        ["image", path, image_path] => read_image(path).save(image_path).unwrap_or_else(|e| fail(&format!("Failed to write {}: {}", image_path, e))),
+        ["text", path] => println!("{}", read_image(path).to_text()),
+        ["debug", path, inputs @ ..] => {
+            let mut machine = read_image(path).machine();
+            machine.extend_inputs(inputs.iter().map(|i| parse_number(i)));
+            println!("Type help for a list of commands");
+            Debugger::new(machine)
+                .repl(BufReader::new(io::stdin()), io::stdout())
+                .unwrap_or_else(|e| fail(&format!("Failed to talk to the terminal: {}", e)));
+        }
+        ["run", path, options @ ..] => run(path, options),
+        ["profile", path, options @ ..] => profile(path, options),
+        ["trace", path, trace_path, options @ ..] => trace(path, trace_path, options),
+        _ => usage(),
+    }
+}
+
+#[derive(Default)]
+struct Options {
+    binary: bool,
+    addresses: Option<Range<usize>>,
+    opcodes: Option<Vec<Opcode>>,
+    limits: Limits,
+    inputs: Vec<i64>,
+}
+
+fn parse_options(options: &[&str]) -> Options {
+    let mut parsed = Options::default();
+
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let mut value = || *options.next().unwrap_or_else(|| usage());
+        match *option {
+            "--binary" => parsed.binary = true,
+            "--addresses" => parsed.addresses = Some(parse_range(value())),
+            "--opcodes" => parsed.opcodes = Some(parse_opcodes(value())),
+            "--max-steps" => parsed.limits.max_steps = Some(parse_count(value())),
+            "--timeout" => parsed.limits.timeout = Some(Duration::from_millis(parse_count(value()))),
+            input => parsed.inputs.push(parse_number(input)),
+        }
+    }
+
+    parsed
+}
+
+fn run(path: &str, options: &[&str]) {
+    let options = parse_options(options);
+
+    let mut machine = read_image(path).machine();
+    machine.extend_inputs(options.inputs);
+    let result = machine.run_limited(options.limits);
+    report(&mut machine, result);
+}
+
+fn profile(path: &str, options: &[&str]) {
+    let options = parse_options(options);
+    let image = read_image(path);
+    let program = image.memory.clone();
+
+    let mut profile = Profile::new();
+    let mut machine = image.machine();
+    machine.extend_inputs(options.inputs);
+    let result = machine.run_limited_with(options.limits, |step| profile.record(step));
+
+    println!("{}", profile.report(&program));
+    println!("{}", profile.annotated_listing(&program));
+    report(&mut machine, result);
+}
+
+fn trace(path: &str, trace_path: &str, options: &[&str]) {
+    let options = parse_options(options);
+
+    let format = if options.binary { Format::Binary } else { Format::JsonLines };
+    let file = File::create(trace_path).unwrap_or_else(|e| fail(&format!("Failed to create {}: {}", trace_path, e)));
+    let mut tracer = Tracer::new(BufWriter::new(file), format);
+    if let Some(range) = options.addresses {
+        tracer = tracer.addresses(range);
+    }
+    if let Some(opcodes) = options.opcodes {
+        tracer = tracer.opcodes(opcodes);
+    }
+
+    let mut machine = read_image(path).machine();
+    machine.extend_inputs(options.inputs);
+    let result = machine.run_limited_with(options.limits, |step| tracer.record(step));
+
+    if let Err(e) = tracer.finish() {
+        fail(&format!("Failed to write {}: {}", trace_path, e));
+    }
+    report(&mut machine, result);
+}
+
+fn report(machine: &mut Machine, result: Result<State, intcode::machine::Error>) {
+    println!("Outputs: {:?}", machine.take_outputs());
+    match result {
+        Ok(State::AwaitingInput) => println!("Stopped waiting for input at {}", machine.ip()),
+        Ok(_) => println!("Halted"),
+        Err(e) => fail(&format!("Error: {}", e)),
+    }
+}
+
+fn parse_range(value: &str) -> Range<usize> {
+    let mut parts = value.splitn(2, "..");
+    match (parts.next().map(str::parse), parts.next().map(str::parse)) {
+        (Some(Ok(start)), Some(Ok(end))) => start..end,
+        _ => fail(&format!("Not a range like 10..20: {}", value)),
+    }
+}
+
+fn parse_opcodes(value: &str) -> Vec<Opcode> {
+    value
+        .split(',')
+        .map(|m| Opcode::from_mnemonic(m).unwrap_or_else(|| fail(&format!("Unknown mnemonic: {}", m))))
+        .collect()
+}
+
+fn read_file(path: &str) -> String {
+    fs::read_to_string(path).unwrap_or_else(|e| fail(&format!("Failed to read {}: {}", path, e)))
+}
+
+// Programs can be given in the comma separated format or as an image
+fn read_image(path: &str) -> Image {
+    image::read(path).unwrap_or_else(|e| fail(&format!("Failed to read {}: {}", path, e)))
+}
+
+fn read_program(path: &str) -> Vec<i64> {
+    read_image(path).memory
+}
+
+fn parse_number(value: &str) -> i64 {
+    value.parse().unwrap_or_else(|_| fail(&format!("Not a number: {}", value)))
+}
+
+// Limits can't be negative
+fn parse_count(value: &str) -> u64 {
+    value.parse().unwrap_or_else(|_| fail(&format!("Not a count: {}", value)))
+}
+
+fn usage() -> ! {
+    eprintln!("Usage:");
+    eprintln!("    intcode asm <source>      Assemble source into the comma separated format");
+    eprintln!("    intcode disasm <program>  Disassemble a comma separated program");
+    eprintln!("    intcode cfg <program>     Print the control flow graph of a program in Graphviz format");
+    eprintln!("    intcode compile <program> Translate a program into Rust source for Machine::run_compiled");
+    eprintln!("    intcode image <program> <image>  Convert a program into the binary image format");
+    eprintln!("    intcode text <image>      Convert an image back into the comma separated format");
+    eprintln!("    intcode debug <program> [input...]  Step through a program interactively");
+    eprintln!("    intcode run <program> [limits] [input...]");
+    eprintln!("                              Run a program and print its outputs");
+    eprintln!("    intcode profile <program> [limits] [input...]");
+    eprintln!("                              Run a program and show where it spent its time");
+    eprintln!("    intcode trace <program> <trace> [--binary] [--addresses 10..20] [--opcodes add,mul] [limits] [input...]");
+    eprintln!("                              Run a program, recording every instruction to a file");
+    eprintln!();
+    eprintln!("Limits are --max-steps <count> and --timeout <milliseconds>");
+    process::exit(2)
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1)
+}
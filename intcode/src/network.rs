@@ -0,0 +1,428 @@
+// Machines connected by packets. Every node is booted with its address as its first input and
+// sends a packet by outputting the destination address followed by the two values, which are
+// queued as input for the node with that address. A node that asks for input while nothing has
+// arrived reads -1, so it can't block the rest of the network.
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::machine;
+use crate::{Limits, Machine, State};
+
+// Instructions a node runs before the next one gets a turn, so a busy node can't starve the rest
+const STEPS_PER_TURN: u64 = 10_000;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Packet {
+    pub from: usize,
+    // Addresses outside the network are only seen by the monitor
+    pub to: i64,
+    pub x: i64,
+    pub y: i64,
+}
+
+// Why a network stopped running
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Stopped {
+    Monitor,
+    // Every node was waiting for input and the monitor had nothing to send
+    Idle,
+    Halted,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Error {
+    pub node: usize,
+    pub error: machine::Error,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "node {}: {}", self.node, self.error)
+    }
+}
+
+impl std::error::Error for Error {}
+
+// Observes the traffic. It sees every packet before it's delivered, and decides what happens
+// when the network goes idle, which is when every node is waiting for input and no packets are
+// on their way.
+pub trait Monitor {
+    // Returning false stops the network
+    fn packet(&mut self, _packet: &Packet) -> bool {
+        true
+    }
+
+    // A packet to wake the network up with, or None to stop it
+    fn idle(&mut self) -> Option<Packet> {
+        None
+    }
+}
+
+// Keeps every packet, and stops the network once it's idle
+#[derive(Clone, Debug, Default)]
+pub struct Log {
+    pub packets: Vec<Packet>,
+}
+
+impl Monitor for Log {
+    fn packet(&mut self, packet: &Packet) -> bool {
+        self.packets.push(*packet);
+        true
+    }
+}
+
+pub struct Network {
+    nodes: Vec<Machine>,
+    empty_input: i64,
+}
+
+impl Network {
+    // `size` nodes running the same program, with addresses 0 to size - 1
+    pub fn new(program: &[i64], size: usize) -> Network {
+        let machine = Machine::new(program.to_vec());
+        let nodes = (0..size)
+            .map(|address| {
+                let mut node = machine.clone();
+                node.push_input(address as i64);
+                node
+            })
+            .collect();
+        Network::from_machines(nodes)
+    }
+
+    // Nodes set up by the caller, addressed by their position. They aren't given their address.
+    pub fn from_machines(nodes: Vec<Machine>) -> Network {
+        Network { nodes, empty_input: -1 }
+    }
+
+    // What a node reads when it asks for input and nothing has arrived, defaults to -1
+    pub fn empty_input(mut self, value: i64) -> Network {
+        self.empty_input = value;
+        self
+    }
+
+    pub fn nodes(&self) -> &[Machine] {
+        &self.nodes
+    }
+
+    // Queues a packet for its destination without showing it to the monitor
+    pub fn send(&mut self, packet: Packet) {
+        if let Some(node) = self.node_mut(packet.to) {
+            node.extend_inputs([packet.x, packet.y]);
+        }
+    }
+
+    fn node_mut(&mut self, address: i64) -> Option<&mut Machine> {
+        usize::try_from(address).ok().and_then(move |address| self.nodes.get_mut(address))
+    }
+
+    // Gives the nodes a turn each, in order of their address, until the monitor stops the
+    // network. Packets are delivered as soon as they're sent, so the same nodes always
+    // give the same traffic.
+    pub fn run<M: Monitor>(&mut self, monitor: &mut M) -> Result<Stopped, Error> {
+        loop {
+            let mut busy = false;
+            for address in 0..self.nodes.len() {
+                let node = &mut self.nodes[address];
+                if node.state() == State::Halted {
+                    continue;
+                }
+
+                let had_input = !node.pending_inputs().is_empty();
+                if node.state() == State::AwaitingInput {
+                    node.push_input(self.empty_input);
+                }
+                let state = take_turn(node).map_err(|error| Error { node: address, error })?;
+
+                let packets = packets(address, node);
+                busy |= had_input || state == State::Running || !packets.is_empty();
+                for packet in packets {
+                    if !monitor.packet(&packet) {
+                        return Ok(Stopped::Monitor);
+                    }
+                    self.send(packet);
+                }
+            }
+
+            if self.nodes.iter().all(|node| node.state() == State::Halted) {
+                return Ok(Stopped::Halted);
+            }
+            if !busy {
+                match monitor.idle() {
+                    Some(packet) => self.send(packet),
+                    None => return Ok(Stopped::Idle),
+                }
+            }
+        }
+    }
+
+    // Same as `run`, but with a thread for every node. Packets go through the calling thread,
+    // which shows them to the monitor before passing them on, so the monitor still sees them
+    // one at a time, but in whatever order the nodes happen to send them.
+    pub fn run_threaded<M: Monitor>(&mut self, monitor: &mut M) -> Result<Stopped, Error> {
+        let empty_input = self.empty_input;
+        let stop = AtomicBool::new(false);
+        let (to_router, from_nodes) = mpsc::channel();
+
+        thread::scope(|scope| {
+            let mut senders = Vec::with_capacity(self.nodes.len());
+            for (address, node) in self.nodes.iter_mut().enumerate() {
+                let (sender, receiver) = mpsc::channel();
+                senders.push(sender);
+                let to_router = to_router.clone();
+                let stop = &stop;
+                scope.spawn(move || run_node(address, node, empty_input, &receiver, &to_router, stop));
+            }
+            drop(to_router);
+
+            let result = route(&senders, &from_nodes, monitor);
+            stop.store(true, Ordering::Relaxed);
+            // Wakes up the nodes waiting for packets
+            drop(senders);
+            result
+        })
+    }
+}
+
+// Runs a node until it needs input it hasn't been given, halts or has used up its turn
+fn take_turn(node: &mut Machine) -> Result<State, machine::Error> {
+    let limits = Limits { max_steps: Some(node.steps() + STEPS_PER_TURN), ..Limits::default() };
+    match node.run_limited(limits) {
+        Err(machine::Error::BudgetExceeded { .. }) => Ok(State::Running),
+        result => result,
+    }
+}
+
+// The complete packets a node has output, anything after the last one stays for later
+fn packets(address: usize, node: &mut Machine) -> Vec<Packet> {
+    let mut packets = Vec::new();
+    while node.outputs().len() >= 3 {
+        let mut next = || node.pop_output().unwrap();
+        packets.push(Packet { from: address, to: next(), x: next(), y: next() });
+    }
+    packets
+}
+
+enum Message {
+    Packet(Packet),
+    // The node read the empty input and did nothing with it, after taking `received` packets
+    Waiting { node: usize, received: usize },
+    Halted { node: usize },
+    Failed(Error),
+}
+
+fn run_node(
+    address: usize,
+    node: &mut Machine,
+    empty_input: i64,
+    packets: &mpsc::Receiver<Packet>,
+    router: &mpsc::Sender<Message>,
+    stop: &AtomicBool,
+) {
+    let mut received = 0;
+    while !stop.load(Ordering::Relaxed) {
+        while let Ok(packet) = packets.try_recv() {
+            node.extend_inputs([packet.x, packet.y]);
+            received += 1;
+        }
+        let waiting = node.state() == State::AwaitingInput;
+        if waiting {
+            node.push_input(empty_input);
+        }
+
+        let state = match take_turn(node) {
+            Ok(state) => state,
+            Err(error) => {
+                let _ = router.send(Message::Failed(Error { node: address, error }));
+                return;
+            }
+        };
+        let sent = packets_sent(address, node, router);
+        if state == State::Halted {
+            let _ = router.send(Message::Halted { node: address });
+            return;
+        }
+
+        // Nothing to do until a packet arrives. The router has all the packets sent before
+        // this message, so it knows whether any are still on their way here.
+        if waiting && !sent && state == State::AwaitingInput {
+            let _ = router.send(Message::Waiting { node: address, received });
+            match packets.recv() {
+                Ok(packet) => {
+                    node.extend_inputs([packet.x, packet.y]);
+                    received += 1;
+                }
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+fn packets_sent(address: usize, node: &mut Machine, router: &mpsc::Sender<Message>) -> bool {
+    let packets = packets(address, node);
+    for packet in &packets {
+        let _ = router.send(Message::Packet(*packet));
+    }
+    !packets.is_empty()
+}
+
+// Passes packets on until the network is idle or stopped. A node counts as idle once it has
+// said it's waiting after taking every packet sent to it.
+fn route<M: Monitor>(senders: &[mpsc::Sender<Packet>], messages: &mpsc::Receiver<Message>, monitor: &mut M) -> Result<Stopped, Error> {
+    let mut sent = vec![0; senders.len()];
+    let mut waiting: Vec<Option<usize>> = vec![None; senders.len()];
+    let mut halted = vec![false; senders.len()];
+
+    let deliver = |sent: &mut Vec<usize>, packet: Packet| {
+        if let Some(address) = usize::try_from(packet.to).ok().filter(|&to| to < senders.len()) {
+            // A halted node has dropped its end, and doesn't need the packet anymore
+            if senders[address].send(packet).is_ok() {
+                sent[address] += 1;
+            }
+        }
+    };
+
+    // Every node has sent at least one message before exiting, so this only ends early when
+    // there are no nodes at all
+    while let Ok(message) = messages.recv() {
+        match message {
+            Message::Packet(packet) => {
+                if !monitor.packet(&packet) {
+                    return Ok(Stopped::Monitor);
+                }
+                deliver(&mut sent, packet);
+            }
+            Message::Waiting { node, received } => waiting[node] = Some(received),
+            Message::Halted { node } => halted[node] = true,
+            Message::Failed(error) => return Err(error),
+        }
+
+        if halted.iter().all(|&halted| halted) {
+            return Ok(Stopped::Halted);
+        }
+        let idle = (0..senders.len()).all(|node| halted[node] || waiting[node] == Some(sent[node]));
+        if idle {
+            match monitor.idle() {
+                Some(packet) => deliver(&mut sent, packet),
+                None => return Ok(Stopped::Idle),
+            }
+        }
+    }
+    Ok(Stopped::Halted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+
+    // Sends (address + 1, address * 10) to the next node, and node 0 starts a ring that every
+    // node passes on with y increased by one. The last node reports to 255 instead.
+    fn ring() -> Vec<i64> {
+        asm::assemble(
+            "in address
+            add address, #1, next
+            eq next, #4, last
+            jf last, #wait
+            add #255, #0, next
+            wait: jt address, #receive
+            out next
+            out #0
+            out #0
+            receive: in x
+            eq x, #-1, empty
+            jt empty, #receive
+            in y
+            add y, #1, y
+            out next
+            out x
+            out y
+            jf #0, #receive
+            address: data 0
+            next: data 0
+            last: data 0
+            x: data 0
+            y: data 0
+            empty: data 0",
+        )
+        .unwrap()
+    }
+
+    // Wakes the network up once, by sending to node 0
+    struct Wake {
+        log: Log,
+        woken: bool,
+    }
+
+    impl Monitor for Wake {
+        fn packet(&mut self, packet: &Packet) -> bool {
+            self.log.packet(packet)
+        }
+
+        fn idle(&mut self) -> Option<Packet> {
+            let first = !self.woken;
+            self.woken = true;
+            first.then_some(Packet { from: 255, to: 0, x: 7, y: 100 })
+        }
+    }
+
+    fn traffic(packets: &[Packet]) -> Vec<(usize, i64, i64, i64)> {
+        packets.iter().map(|p| (p.from, p.to, p.x, p.y)).collect()
+    }
+
+    fn woken() -> Wake {
+        Wake { log: Log::default(), woken: false }
+    }
+
+    const RING_TRAFFIC: [(usize, i64, i64, i64); 8] =
+        [(0, 1, 0, 0), (1, 2, 0, 1), (2, 3, 0, 2), (3, 255, 0, 3), (0, 1, 7, 101), (1, 2, 7, 102), (2, 3, 7, 103), (3, 255, 7, 104)];
+
+    #[test]
+    fn routes_packets_round_robin() {
+        let mut monitor = woken();
+        assert_eq!(Network::new(&ring(), 4).run(&mut monitor), Ok(Stopped::Idle));
+        assert_eq!(traffic(&monitor.log.packets), RING_TRAFFIC);
+    }
+
+    #[test]
+    fn routes_packets_across_threads() {
+        // The ring passes one packet at a time, so the order is fixed even with threads
+        for _ in 0..20 {
+            let mut monitor = woken();
+            assert_eq!(Network::new(&ring(), 4).run_threaded(&mut monitor), Ok(Stopped::Idle));
+            assert_eq!(traffic(&monitor.log.packets), RING_TRAFFIC);
+        }
+    }
+
+    #[test]
+    fn monitor_can_stop_the_network() {
+        struct FirstToMonitor(Option<Packet>);
+        impl Monitor for FirstToMonitor {
+            fn packet(&mut self, packet: &Packet) -> bool {
+                if packet.to == 255 {
+                    self.0 = Some(*packet);
+                }
+                self.0.is_none()
+            }
+        }
+
+        let mut monitor = FirstToMonitor(None);
+        assert_eq!(Network::new(&ring(), 4).run_threaded(&mut monitor), Ok(Stopped::Monitor));
+        assert_eq!(monitor.0, Some(Packet { from: 3, to: 255, x: 0, y: 3 }));
+    }
+
+    #[test]
+    fn stops_when_every_node_halts_or_fails() {
+        let program = asm::assemble("in a\nout a\nout a\nout a\nhlt\na: data 0").unwrap();
+        let mut log = Log::default();
+        assert_eq!(Network::new(&program, 3).run(&mut log), Ok(Stopped::Halted));
+        assert_eq!(traffic(&log.packets), vec![(0, 0, 0, 0), (1, 1, 1, 1), (2, 2, 2, 2)]);
+        assert_eq!(Network::new(&program, 3).run_threaded(&mut Log::default()), Ok(Stopped::Halted));
+
+        let failing = Network::from_machines(vec![Machine::new(vec![3, 0, 99]), Machine::new(vec![42])]).run(&mut Log::default());
+        assert_eq!(failing, Err(Error { node: 1, error: machine::Error::InvalidInstruction { address: 0, word: 42 } }));
+    }
+}
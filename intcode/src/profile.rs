@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+use crate::disasm::{self, Item};
+use crate::machine::Step;
+use crate::Opcode;
+
+const REPORT_ROWS: usize = 10;
+const BAR_WIDTH: usize = 10;
+
+// Execution counts collected from the steps of a run, use `record` as the step callback
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Profile {
+    executions: Vec<u64>,
+    reads: Vec<u64>,
+    writes: Vec<u64>,
+    opcodes: BTreeMap<Opcode, u64>,
+    steps: u64,
+    inputs: u64,
+    outputs: u64,
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile::default()
+    }
+
+    pub fn record(&mut self, step: &Step) {
+        self.steps += 1;
+        increment(&mut self.executions, step.address);
+        *self.opcodes.entry(step.instruction.opcode).or_insert(0) += 1;
+
+        let opcode = step.instruction.opcode;
+        let read_count = opcode.parameter_count() - opcode.writes_memory() as usize;
+        for operand in &step.operands()[..read_count] {
+            if let Some(address) = operand.address {
+                increment(&mut self.reads, address);
+            }
+        }
+
+        if let Some(write) = step.write {
+            increment(&mut self.writes, write.address);
+        }
+        self.inputs += step.input.is_some() as u64;
+        self.outputs += step.output.is_some() as u64;
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn executions(&self, address: usize) -> u64 {
+        self.executions.get(address).copied().unwrap_or(0)
+    }
+
+    pub fn reads(&self, address: usize) -> u64 {
+        self.reads.get(address).copied().unwrap_or(0)
+    }
+
+    pub fn writes(&self, address: usize) -> u64 {
+        self.writes.get(address).copied().unwrap_or(0)
+    }
+
+    pub fn opcode_count(&self, opcode: Opcode) -> u64 {
+        self.opcodes.get(&opcode).copied().unwrap_or(0)
+    }
+
+    pub fn inputs(&self) -> u64 {
+        self.inputs
+    }
+
+    pub fn outputs(&self) -> u64 {
+        self.outputs
+    }
+
+    // Summary of the busiest addresses, opcodes and memory cells
+    pub fn report(&self, program: &[i64]) -> String {
+        let mut report = String::new();
+        writeln!(report, "Executed {} instructions, {} inputs, {} outputs", self.steps, self.inputs, self.outputs).unwrap();
+
+        writeln!(report, "\nHottest instructions:").unwrap();
+        for (address, count) in hottest(&self.executions) {
+            writeln!(report, "{:>12} {:>6}: {}", count, address, disasm::decode_at(program, address)).unwrap();
+        }
+
+        writeln!(report, "\nOpcodes:").unwrap();
+        let mut opcodes: Vec<(&Opcode, &u64)> = self.opcodes.iter().collect();
+        opcodes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (opcode, count) in opcodes {
+            writeln!(report, "{:>12} {}", count, opcode).unwrap();
+        }
+
+        for (title, counts) in [("Most read cells", &self.reads), ("Most written cells", &self.writes)] {
+            writeln!(report, "\n{}:", title).unwrap();
+            for (address, count) in hottest(counts) {
+                writeln!(report, "{:>12} {:>6}", count, address).unwrap();
+            }
+        }
+
+        report
+    }
+
+    // Disassembly of the program with the execution count of every instruction. Addresses that
+    // were executed always start an instruction, even if a plain disassembly would disagree.
+    pub fn annotated_listing(&self, program: &[i64]) -> String {
+        let max = self.executions.iter().copied().max().unwrap_or(0).max(1);
+        let mut listing = String::new();
+
+        let mut address = 0;
+        while address < program.len() {
+            let mut item = disasm::decode_at(program, address);
+            if (address + 1..address + item.length()).any(|a| self.executions(a) > 0) {
+                item = Item::Data(program[address]);
+            }
+
+            let count = self.executions(address);
+            let column = if count > 0 { count.to_string() } else { String::new() };
+            let bar = "#".repeat((count * BAR_WIDTH as u64).div_ceil(max) as usize);
+            writeln!(listing, "{:>12} {:<width$} {:>6}: {}", column, bar, address, item, width = BAR_WIDTH).unwrap();
+
+            address += item.length();
+        }
+
+        listing
+    }
+}
+
+fn increment(counts: &mut Vec<u64>, address: usize) {
+    if address >= counts.len() {
+        counts.resize(address + 1, 0);
+    }
+    counts[address] += 1;
+}
+
+fn hottest(counts: &[u64]) -> Vec<(usize, u64)> {
+    let mut hot: Vec<(usize, u64)> = counts.iter().copied().enumerate().filter(|(_, count)| *count > 0).collect();
+    hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    hot.truncate(REPORT_ROWS);
+    hot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm, Machine};
+
+    fn countdown() -> Vec<i64> {
+        asm::assemble(
+            "
+                    in   n
+            loop:   add  n, #-1, n
+                    jt   n, #loop
+                    out  n
+                    hlt
+            n:      data 0
+            ",
+        )
+        .unwrap()
+    }
+
+    fn profile(program: &[i64], input: i64) -> Profile {
+        let mut profile = Profile::new();
+        let mut machine = Machine::new(program.to_vec());
+        machine.push_input(input);
+        machine.run_with(|step| profile.record(step)).unwrap();
+        profile
+    }
+
+    #[test]
+    fn counts_executions() {
+        let profile = profile(&countdown(), 5);
+        assert_eq!(profile.steps(), 13);
+        assert_eq!(profile.executions(0), 1);
+        assert_eq!(profile.executions(2), 5);
+        assert_eq!(profile.executions(6), 5);
+        assert_eq!(profile.opcode_count(Opcode::JumpIfTrue), 5);
+        assert_eq!(profile.opcode_count(Opcode::Halt), 1);
+        assert_eq!(profile.reads(12), 11);
+        assert_eq!(profile.writes(12), 6);
+        assert_eq!((profile.inputs(), profile.outputs()), (1, 1));
+    }
+
+    #[test]
+    fn reports_hottest_first() {
+        let program = countdown();
+        let report = profile(&program, 5).report(&program);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], "Executed 13 instructions, 1 inputs, 1 outputs");
+        assert_eq!(lines[3], "           5      2: add 12, #-1, 12");
+        assert_eq!(lines[4], "           5      6: jt 12, #2");
+    }
+
+    #[test]
+    fn annotates_listing() {
+        let program = countdown();
+        let listing = profile(&program, 5).annotated_listing(&program);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines[0], "           1 ##              0: in 12");
+        assert_eq!(lines[1], "           5 ##########      2: add 12, #-1, 12");
+        assert_eq!(lines[5], "                            12: data 0");
+    }
+
+    #[test]
+    fn executed_addresses_start_instructions() {
+        // Jumps over the data word at 3, which linear disassembly would read as an instruction
+        let program = vec![1105, 1, 4, 4, 99];
+        let listing = profile(&program, 0).annotated_listing(&program);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines[1], "                             3: data 4");
+        assert_eq!(lines[2], "           1 ##########      4: hlt");
+    }
+}
@@ -0,0 +1,196 @@
+// Runs a program once for every candidate in a search space, spread over threads, and keeps
+// the candidate that scores best. Every run starts from a fork of the same machine, so memory
+// is only copied for the cells a run changes.
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::{Limits, Machine};
+
+#[derive(Clone, Debug)]
+pub struct Found<C, S> {
+    pub candidate: C,
+    pub score: S,
+    // The machine as the run left it, with its outputs
+    pub machine: Machine,
+}
+
+pub struct Search<C> {
+    machine: Machine,
+    candidates: Vec<C>,
+    threads: usize,
+    limits: Limits,
+}
+
+impl<C: Sync> Search<C> {
+    pub fn new<I: IntoIterator<Item = C>>(machine: Machine, candidates: I) -> Search<C> {
+        Search {
+            machine,
+            candidates: candidates.into_iter().collect(),
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            limits: Limits::default(),
+        }
+    }
+
+    // Defaults to the number of cores
+    pub fn threads(mut self, threads: usize) -> Search<C> {
+        self.threads = threads.max(1);
+        self
+    }
+
+    // Limits for each run, runs that go over them count as not matching
+    pub fn limits(mut self, limits: Limits) -> Search<C> {
+        self.limits = limits;
+        self
+    }
+
+    // Finds the first candidate, in the order they were given, whose run matches. Candidates
+    // after one that has already matched aren't started.
+    pub fn first<P, M>(self, prepare: P, matches: M) -> Option<Found<C, ()>>
+    where
+        P: Fn(&C, &mut Machine) + Sync,
+        M: Fn(&C, &Machine) -> bool + Sync,
+    {
+        self.run(prepare, |candidate, machine| matches(candidate, machine).then_some(()), true)
+    }
+
+    // Runs every candidate and returns the one with the highest score. Candidates the objective
+    // returns None for are left out, ties go to the earliest candidate.
+    pub fn best<P, O, S>(self, prepare: P, objective: O) -> Option<Found<C, S>>
+    where
+        P: Fn(&C, &mut Machine) + Sync,
+        O: Fn(&C, &Machine) -> Option<S> + Sync,
+        S: Ord + Send,
+    {
+        self.run(prepare, objective, false)
+    }
+
+    // `prepare` sets up a fork for the candidate by patching memory or queueing input, then the
+    // fork is run until it halts or waits for input. Runs that fail are skipped.
+    fn run<P, O, S>(mut self, prepare: P, objective: O, stop_at_first: bool) -> Option<Found<C, S>>
+    where
+        P: Fn(&C, &mut Machine) + Sync,
+        O: Fn(&C, &Machine) -> Option<S> + Sync,
+        S: Ord + Send,
+    {
+        let next = AtomicUsize::new(0);
+        // Lowest index that has matched so far, only used when stopping at the first match
+        let first_match = AtomicUsize::new(usize::MAX);
+
+        let worker = || {
+            let mut best: Option<(usize, S, Machine)> = None;
+            loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                if index >= self.candidates.len() || (stop_at_first && index > first_match.load(Ordering::Relaxed)) {
+                    return best;
+                }
+
+                let candidate = &self.candidates[index];
+                let mut machine = self.machine.clone();
+                prepare(candidate, &mut machine);
+                if machine.run_limited(self.limits).is_err() {
+                    continue;
+                }
+
+                if let Some(score) = objective(candidate, &machine) {
+                    // Each thread takes indices in increasing order, so only a higher score can
+                    // beat what it has already found
+                    if best.as_ref().is_none_or(|(_, best_score, _)| score > *best_score) {
+                        best = Some((index, score, machine));
+                    }
+                    if stop_at_first {
+                        first_match.fetch_min(index, Ordering::Relaxed);
+                        return best;
+                    }
+                }
+            }
+        };
+
+        let results: Vec<(usize, S, Machine)> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..self.threads).map(|_| scope.spawn(worker)).collect();
+            handles.into_iter().filter_map(|handle| handle.join().unwrap()).collect()
+        });
+
+        let (index, score, machine) = results.into_iter().max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))?;
+        Some(Found { candidate: self.candidates.swap_remove(index), score, machine })
+    }
+}
+
+// Every ordering of the items, for searches like the amplifier phase settings
+pub fn permutations<T: Clone>(items: &[T]) -> Vec<Vec<T>> {
+    if items.len() <= 1 {
+        return vec![items.to_vec()];
+    }
+
+    let mut result = Vec::new();
+    for index in 0..items.len() {
+        let mut rest = items.to_vec();
+        let first = rest.remove(index);
+        for mut permutation in permutations(&rest) {
+            permutation.insert(0, first.clone());
+            result.push(permutation);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+
+    fn noun_and_verb_search(threads: usize, target: i64) -> Option<Found<(i64, i64), ()>> {
+        // Cell 0 ends up as 11 * (noun + verb), like the day2 programs it's a sum of the two
+        let mut program = vec![1, 0, 0, 3, 1, 1, 2, 3, 2, 3, 13, 0, 99, 11];
+        program.resize(100, 0);
+
+        let candidates = (0..100).flat_map(|noun| (0..100).map(move |verb| (noun, verb)));
+        Search::new(Machine::new(program), candidates).threads(threads).first(
+            |&(noun, verb), machine| {
+                machine.write(1, noun);
+                machine.write(2, verb);
+            },
+            |_, machine| machine.read(0) == target,
+        )
+    }
+
+    #[test]
+    fn finds_the_first_match() {
+        for threads in 1..=8 {
+            let found = noun_and_verb_search(threads, 792).unwrap();
+            assert_eq!(found.candidate, (0, 72));
+            assert_eq!(found.machine.read(3), 72);
+        }
+        assert!(noun_and_verb_search(4, 793).is_none());
+    }
+
+    #[test]
+    fn finds_the_best_candidate() {
+        let program = asm::assemble("in a\nin b\nmul a, #10, a\nadd a, b, a\nout a\nhlt\na: data 0\nb: data 0").unwrap();
+        let found = Search::new(Machine::new(program), permutations(&[1, 2, 3]))
+            .threads(3)
+            .best(|phases, machine| machine.extend_inputs(phases.iter().copied()), |_, machine| machine.outputs().back().copied())
+            .unwrap();
+
+        assert_eq!(found.candidate, vec![3, 2, 1]);
+        assert_eq!(found.score, 32);
+        assert_eq!(found.machine.outputs(), &[32]);
+    }
+
+    #[test]
+    fn skips_failing_runs() {
+        // Jumps to the input, -5 isn't an address and 6 loops forever
+        let program = vec![3, 100, 105, 1, 100, 99, 1105, 1, 6];
+        let limits = Limits { max_steps: Some(100), ..Limits::default() };
+        let found = Search::new(Machine::new(program), vec![-5, 6, 5, 0])
+            .limits(limits)
+            .best(|&input, machine| machine.push_input(input), |&input, _| Some(input))
+            .unwrap();
+        assert_eq!(found.candidate, 5);
+    }
+
+    #[test]
+    fn permutes() {
+        assert_eq!(permutations(&[1, 2, 3]), vec![vec![1, 2, 3], vec![1, 3, 2], vec![2, 1, 3], vec![2, 3, 1], vec![3, 1, 2], vec![3, 2, 1]]);
+        assert_eq!(permutations(&[0; 5]).len(), 120);
+    }
+}
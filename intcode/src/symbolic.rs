@@ -0,0 +1,326 @@
+// Runs a program with some memory cells left as variables, tracking every other cell as a
+// polynomial of them. Only works as long as control flow and addresses don't depend on the
+// variables, anything else is reported as an error so the caller can fall back to running
+// the program for every combination of values.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::{Add, Mul, RangeInclusive};
+
+use crate::machine::MAX_MEMORY;
+use crate::{Instruction, Mode, Opcode};
+
+// Programs that run longer than this are most likely looping on something symbolic
+const MAX_STEPS: u64 = 1_000_000;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+    InvalidInstruction { address: usize, word: i64 },
+    InvalidAddress { address: usize, target: i64 },
+    // A value depending on the variables was needed as an opcode, address, jump target or condition
+    NotConcrete { address: usize },
+    Input { address: usize },
+    BudgetExceeded { address: usize },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidInstruction { address, word } => write!(f, "invalid instruction {} at {}", word, address),
+            Error::InvalidAddress { address, target } => write!(f, "invalid address {} used at {}", target, address),
+            Error::NotConcrete { address } => write!(f, "instruction at {} depends on the variables", address),
+            Error::Input { address } => write!(f, "instruction at {} reads input", address),
+            Error::BudgetExceeded { address } => write!(f, "gave up at {} after {} steps", address, MAX_STEPS),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// Sum of terms, each a coefficient times a product of variables. Variables are named by the
+// address of the cell they started in, and a term can contain the same variable several times.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Polynomial {
+    terms: BTreeMap<Vec<usize>, i64>,
+}
+
+impl Polynomial {
+    pub fn constant(value: i64) -> Polynomial {
+        let mut terms = BTreeMap::new();
+        if value != 0 {
+            terms.insert(Vec::new(), value);
+        }
+        Polynomial { terms }
+    }
+
+    pub fn variable(address: usize) -> Polynomial {
+        let mut terms = BTreeMap::new();
+        terms.insert(vec![address], 1);
+        Polynomial { terms }
+    }
+
+    pub fn as_constant(&self) -> Option<i64> {
+        match self.terms.iter().next() {
+            None => Some(0),
+            Some((variables, value)) if variables.is_empty() => Some(*value),
+            _ => None,
+        }
+    }
+
+    // Highest number of times the variable occurs in a single term
+    pub fn degree(&self, variable: usize) -> usize {
+        self.terms.keys().map(|variables| variables.iter().filter(|v| **v == variable).count()).max().unwrap_or(0)
+    }
+
+    // Replaces a variable with a value
+    pub fn substitute(&self, variable: usize, value: i64) -> Polynomial {
+        let mut result = Polynomial::default();
+        for (variables, coefficient) in &self.terms {
+            let mut remaining = variables.clone();
+            let mut coefficient = *coefficient;
+            while let Some(index) = remaining.iter().position(|v| *v == variable) {
+                remaining.remove(index);
+                coefficient = coefficient.wrapping_mul(value);
+            }
+            result.add_term(remaining, coefficient);
+        }
+        result
+    }
+
+    pub fn evaluate(&self, values: &[(usize, i64)]) -> Option<i64> {
+        values.iter().fold(self.clone(), |polynomial, &(variable, value)| polynomial.substitute(variable, value)).as_constant()
+    }
+
+    fn add_term(&mut self, variables: Vec<usize>, coefficient: i64) {
+        let sum = self.terms.get(&variables).copied().unwrap_or(0).wrapping_add(coefficient);
+        if sum == 0 {
+            self.terms.remove(&variables);
+        } else {
+            self.terms.insert(variables, sum);
+        }
+    }
+}
+
+impl Add for Polynomial {
+    type Output = Polynomial;
+
+    fn add(mut self, other: Polynomial) -> Polynomial {
+        for (variables, coefficient) in other.terms {
+            self.add_term(variables, coefficient);
+        }
+        self
+    }
+}
+
+impl Mul for Polynomial {
+    type Output = Polynomial;
+
+    fn mul(self, other: Polynomial) -> Polynomial {
+        let mut result = Polynomial::default();
+        for (a, x) in &self.terms {
+            for (b, y) in &other.terms {
+                let mut variables: Vec<usize> = a.iter().chain(b).copied().collect();
+                variables.sort_unstable();
+                result.add_term(variables, x.wrapping_mul(*y));
+            }
+        }
+        result
+    }
+}
+
+impl fmt::Display for Polynomial {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.terms.is_empty() {
+            return write!(f, "0");
+        }
+
+        // Highest degree first, the constant goes last
+        let mut terms: Vec<(&Vec<usize>, &i64)> = self.terms.iter().collect();
+        terms.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then(a.0.cmp(b.0)));
+        for (index, (variables, coefficient)) in terms.into_iter().enumerate() {
+            let sign = if *coefficient < 0 { "-" } else { "+" };
+            match index {
+                0 if *coefficient < 0 => write!(f, "-")?,
+                0 => {}
+                _ => write!(f, " {} ", sign)?,
+            }
+
+            let magnitude = coefficient.unsigned_abs();
+            let factors: Vec<String> = variables.iter().map(|v| format!("[{}]", v)).collect();
+            match (magnitude, factors.is_empty()) {
+                (_, true) => write!(f, "{}", magnitude)?,
+                (1, false) => write!(f, "{}", factors.join("*"))?,
+                _ => write!(f, "{}*{}", magnitude, factors.join("*"))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// Runs the program until it halts, with the cells at `variables` left unknown. Cells read
+// through an address that depends on the variables are None, which is only an error if the
+// value ends up being needed.
+pub fn execute(program: &[i64], variables: &[usize]) -> Result<Vec<Option<Polynomial>>, Error> {
+    let mut memory: Vec<Option<Polynomial>> = program.iter().map(|&cell| Some(Polynomial::constant(cell))).collect();
+    for &variable in variables {
+        if variable >= memory.len() {
+            memory.resize(variable + 1, Some(Polynomial::default()));
+        }
+        memory[variable] = Some(Polynomial::variable(variable));
+    }
+
+    let mut ip = 0;
+    let mut relative_base = 0i64;
+    for _ in 0..MAX_STEPS {
+        let word = concrete(&memory, ip, ip)?;
+        let instruction = Instruction::decode(word).ok_or(Error::InvalidInstruction { address: ip, word })?;
+        let opcode = instruction.opcode;
+
+        let count = opcode.parameter_count();
+        let mut targets = [None; 3];
+        // Left as None for cells read through an unknown address
+        let mut values: [Option<Polynomial>; 3] = Default::default();
+        for index in 0..count {
+            let raw = concrete(&memory, ip + 1 + index, ip);
+            let target = match (instruction.modes[index], raw) {
+                (Mode::Immediate, _) => None,
+                (Mode::Position, Ok(raw)) => Some(check_address(ip, raw)?),
+                (Mode::Relative, Ok(raw)) => Some(check_address(ip, relative_base.wrapping_add(raw))?),
+                // An unknown address is fine to read from, but not to write to
+                (_, Err(e)) if opcode.writes_memory() && index == count - 1 => return Err(e),
+                (_, Err(_)) => continue,
+            };
+            targets[index] = target;
+            values[index] = read(&memory, target.unwrap_or(ip + 1 + index));
+        }
+
+        let condition = |value: &Option<Polynomial>| value.as_ref().and_then(Polynomial::as_constant).ok_or(Error::NotConcrete { address: ip });
+        let mut next = ip + instruction.length();
+        let result = match opcode {
+            Opcode::Add => Some(values[0].clone().zip(values[1].clone()).map(|(a, b)| a + b)),
+            Opcode::Multiply => Some(values[0].clone().zip(values[1].clone()).map(|(a, b)| a * b)),
+            Opcode::LessThan => Some(Some(Polynomial::constant((condition(&values[0])? < condition(&values[1])?) as i64))),
+            Opcode::Equals => Some(Some(Polynomial::constant((condition(&values[0])? == condition(&values[1])?) as i64))),
+            Opcode::Input => return Err(Error::Input { address: ip }),
+            Opcode::Output => None,
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+                if (condition(&values[0])? != 0) == (opcode == Opcode::JumpIfTrue) {
+                    next = check_address(ip, condition(&values[1])?)?;
+                }
+                None
+            }
+            Opcode::AdjustRelativeBase => {
+                relative_base = relative_base.wrapping_add(condition(&values[0])?);
+                None
+            }
+            Opcode::Halt => return Ok(memory),
+        };
+
+        if let Some(value) = result {
+            let target = targets[count - 1].unwrap();
+            if target >= memory.len() {
+                memory.resize(target + 1, Some(Polynomial::default()));
+            }
+            memory[target] = value;
+        }
+        ip = next;
+    }
+
+    Err(Error::BudgetExceeded { address: ip })
+}
+
+fn read(memory: &[Option<Polynomial>], address: usize) -> Option<Polynomial> {
+    memory.get(address).cloned().unwrap_or_else(|| Some(Polynomial::default()))
+}
+
+fn concrete(memory: &[Option<Polynomial>], address: usize, ip: usize) -> Result<i64, Error> {
+    match memory.get(address) {
+        None => Ok(0),
+        Some(value) => value.as_ref().and_then(Polynomial::as_constant).ok_or(Error::NotConcrete { address: ip }),
+    }
+}
+
+fn check_address(address: usize, target: i64) -> Result<usize, Error> {
+    if target < 0 || target as usize >= MAX_MEMORY {
+        Err(Error::InvalidAddress { address, target })
+    } else {
+        Ok(target as usize)
+    }
+}
+
+// Finds values for the variables, each within its range, that make the polynomial equal the
+// target. The first variable that is left and only appears linearly is solved for directly,
+// any others are tried one value at a time. Values are tried in increasing order, so this finds
+// the same solution as nested loops over the ranges would.
+pub fn solve(polynomial: &Polynomial, target: i64, ranges: &[(usize, RangeInclusive<i64>)]) -> Option<Vec<i64>> {
+    match ranges {
+        [] => (polynomial.as_constant() == Some(target)).then(Vec::new),
+        [(variable, range)] if polynomial.degree(*variable) <= 1 => {
+            let rest = polynomial.substitute(*variable, 0).as_constant()?;
+            let coefficient = polynomial.substitute(*variable, 1).as_constant()?.wrapping_sub(rest);
+            let value = if coefficient == 0 {
+                Some(*range.start()).filter(|_| rest == target)
+            } else {
+                let difference = target.wrapping_sub(rest);
+                Some(difference / coefficient).filter(|_| difference % coefficient == 0)
+            }?;
+
+            // Overflow can make the division disagree with wrapping arithmetic, so check it
+            let check = polynomial.substitute(*variable, value).as_constant();
+            (range.contains(&value) && check == Some(target)).then(|| vec![value])
+        }
+        [(variable, range), rest @ ..] => range.clone().find_map(|value| {
+            let mut solution = solve(&polynomial.substitute(*variable, value), target, rest)?;
+            solution.insert(0, value);
+            Some(solution)
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+
+    #[test]
+    fn multiplies_polynomials() {
+        let x = Polynomial::variable(1);
+        let y = Polynomial::variable(2);
+        let p = (x.clone() + Polynomial::constant(3)) * (x.clone() + y.clone() * Polynomial::constant(-2));
+        assert_eq!(p.to_string(), "[1]*[1] - 2*[1]*[2] + 3*[1] - 6*[2]");
+        assert_eq!(p.degree(1), 2);
+        assert_eq!(p.evaluate(&[(1, 2), (2, 5)]), Some(-40));
+        assert_eq!((x.clone() + Polynomial::constant(-1) * x).as_constant(), Some(0));
+    }
+
+    #[test]
+    fn tracks_cells_through_day2_style_programs() {
+        // The first instruction reads through the variables, but its result is overwritten
+        let program = vec![1, 0, 0, 3, 1, 1, 2, 3, 2, 3, 13, 0, 99, 11];
+        let memory = execute(&program, &[1, 2]).unwrap();
+        assert_eq!(memory[0].as_ref().unwrap().to_string(), "11*[1] + 11*[2]");
+        assert_eq!(memory[3].as_ref().unwrap().to_string(), "[1] + [2]");
+    }
+
+    #[test]
+    fn solves_like_a_brute_force_search() {
+        let polynomial = Polynomial::variable(1) * Polynomial::constant(300) + Polynomial::variable(2) + Polynomial::constant(7);
+        let ranges = [(1, 0..=99), (2, 0..=99)];
+        assert_eq!(solve(&polynomial, 9_049, &ranges), Some(vec![30, 42]));
+        // 0 and 300 is out of range, so the next noun is the first that works
+        assert_eq!(solve(&polynomial, 307, &ranges), Some(vec![1, 0]));
+        assert_eq!(solve(&polynomial, 50_000, &ranges), None);
+
+        let square = Polynomial::variable(1) * Polynomial::variable(1);
+        assert_eq!(solve(&square, 49, &[(1, -10..=10)]), Some(vec![-7]));
+    }
+
+    #[test]
+    fn rejects_control_flow_on_variables() {
+        let program = asm::assemble("jt x, #3\nhlt\nx: data 0").unwrap();
+        assert_eq!(execute(&program, &[4]), Err(Error::NotConcrete { address: 0 }));
+
+        let program = asm::assemble("add #1, #2, @0\nhlt").unwrap();
+        assert!(execute(&program, &[]).is_ok());
+        assert_eq!(execute(&[1101, 1, 2, 5, 99, 0], &[3]), Err(Error::NotConcrete { address: 0 }));
+    }
+}
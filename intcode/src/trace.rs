@@ -0,0 +1,273 @@
+// Records executed instructions to a file for later analysis. Nothing is recorded unless a
+// tracer is passed to `Machine::run_with`, so plain runs pay nothing for it.
+use std::collections::BTreeSet;
+use std::io::{self, Read};
+use std::ops::Range;
+
+use crate::disasm::Item;
+use crate::machine::{Step, Write};
+use crate::{varint, Instruction, Opcode};
+
+const MAGIC: &[u8; 4] = b"ICT\x01";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    JsonLines,
+    Binary,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Record {
+    pub step: u64,
+    pub ip: usize,
+    pub instruction: Instruction,
+    pub parameters: Vec<i64>,
+    // Values of the parameters the instruction reads, the written one is covered by `write`
+    pub operands: Vec<i64>,
+    pub write: Option<Write>,
+    pub input: Option<i64>,
+    pub output: Option<i64>,
+}
+
+impl Record {
+    pub fn new(step_number: u64, step: &Step) -> Record {
+        let opcode = step.instruction.opcode;
+        let read_count = opcode.parameter_count() - opcode.writes_memory() as usize;
+
+        Record {
+            step: step_number,
+            ip: step.address,
+            instruction: step.instruction,
+            parameters: step.operands().iter().map(|o| o.raw).collect(),
+            operands: step.operands()[..read_count].iter().map(|o| o.value).collect(),
+            write: step.write,
+            input: step.input,
+            output: step.output,
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let item = Item::Instruction { instruction: self.instruction, parameters: self.parameters.clone() };
+        let operands: Vec<String> = self.operands.iter().map(|v| v.to_string()).collect();
+        let write = match self.write {
+            Some(w) => format!("{{\"address\":{},\"old\":{},\"new\":{}}}", w.address, w.old, w.new),
+            None => "null".to_string(),
+        };
+        let optional = |value: Option<i64>| value.map_or("null".to_string(), |v| v.to_string());
+
+        format!(
+            "{{\"step\":{},\"ip\":{},\"opcode\":\"{}\",\"instruction\":\"{}\",\"operands\":[{}],\"write\":{},\"input\":{},\"output\":{}}}",
+            self.step,
+            self.ip,
+            self.instruction.opcode,
+            item,
+            operands.join(","),
+            write,
+            optional(self.input),
+            optional(self.output)
+        )
+    }
+
+    fn write_binary<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        varint::write_u64(writer, self.step)?;
+        varint::write_u64(writer, self.ip as u64)?;
+        varint::write_i64(writer, self.instruction.encode())?;
+        for value in self.parameters.iter().chain(&self.operands) {
+            varint::write_i64(writer, *value)?;
+        }
+
+        let flags = self.write.is_some() as u8 | (self.input.is_some() as u8) << 1 | (self.output.is_some() as u8) << 2;
+        writer.write_all(&[flags])?;
+
+        if let Some(w) = self.write {
+            varint::write_u64(writer, w.address as u64)?;
+            varint::write_i64(writer, w.old)?;
+            varint::write_i64(writer, w.new)?;
+        }
+        for value in self.input.iter().chain(&self.output) {
+            varint::write_i64(writer, *value)?;
+        }
+
+        Ok(())
+    }
+
+    fn read_binary<R: Read>(reader: &mut R) -> io::Result<Option<Record>> {
+        let step = match varint::read_u64(reader)? {
+            Some(step) => step,
+            None => return Ok(None),
+        };
+        let ip = varint::expect_u64(reader)? as usize;
+        let word = varint::expect_i64(reader)?;
+        let instruction = Instruction::decode(word).ok_or_else(|| invalid(format!("invalid instruction {} in trace", word)))?;
+
+        let opcode = instruction.opcode;
+        let count = opcode.parameter_count();
+        let parameters = (0..count).map(|_| varint::expect_i64(reader)).collect::<io::Result<_>>()?;
+        let operands = (0..count - opcode.writes_memory() as usize)
+            .map(|_| varint::expect_i64(reader))
+            .collect::<io::Result<_>>()?;
+
+        let mut flags = [0u8];
+        reader.read_exact(&mut flags)?;
+        let flags = flags[0];
+
+        let write = if flags & 1 != 0 {
+            Some(Write {
+                address: varint::expect_u64(reader)? as usize,
+                old: varint::expect_i64(reader)?,
+                new: varint::expect_i64(reader)?,
+            })
+        } else {
+            None
+        };
+        let input = if flags & 2 != 0 { Some(varint::expect_i64(reader)?) } else { None };
+        let output = if flags & 4 != 0 { Some(varint::expect_i64(reader)?) } else { None };
+
+        Ok(Some(Record { step, ip, instruction, parameters, operands, write, input, output }))
+    }
+}
+
+pub struct Tracer<W: io::Write> {
+    writer: W,
+    format: Format,
+    addresses: Option<Range<usize>>,
+    opcodes: Option<BTreeSet<Opcode>>,
+    steps: u64,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> Tracer<W> {
+    pub fn new(mut writer: W, format: Format) -> Tracer<W> {
+        let error = match format {
+            Format::Binary => writer.write_all(MAGIC).err(),
+            Format::JsonLines => None,
+        };
+
+        Tracer { writer, format, addresses: None, opcodes: None, steps: 0, error }
+    }
+
+    // Only record instructions located in the range
+    pub fn addresses(mut self, range: Range<usize>) -> Tracer<W> {
+        self.addresses = Some(range);
+        self
+    }
+
+    // Only record instructions with one of the opcodes
+    pub fn opcodes<I: IntoIterator<Item = Opcode>>(mut self, opcodes: I) -> Tracer<W> {
+        self.opcodes = Some(opcodes.into_iter().collect());
+        self
+    }
+
+    // Write errors are kept until `finish`, so this can be used directly as a step callback
+    pub fn record(&mut self, step: &Step) {
+        let number = self.steps;
+        self.steps += 1;
+
+        if self.error.is_some() || !self.matches(step) {
+            return;
+        }
+
+        let record = Record::new(number, step);
+        let result = match self.format {
+            Format::JsonLines => writeln!(self.writer, "{}", record.to_json()),
+            Format::Binary => record.write_binary(&mut self.writer),
+        };
+        self.error = result.err();
+    }
+
+    fn matches(&self, step: &Step) -> bool {
+        let in_range = self.addresses.as_ref().is_none_or(|range| range.contains(&step.address));
+        let has_opcode = self.opcodes.as_ref().is_none_or(|opcodes| opcodes.contains(&step.instruction.opcode));
+        in_range && has_opcode
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+pub fn read_binary<R: Read>(mut reader: R) -> io::Result<Vec<Record>> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a binary trace".to_string()));
+    }
+
+    let mut records = Vec::new();
+    while let Some(record) = Record::read_binary(&mut reader)? {
+        records.push(record);
+    }
+    Ok(records)
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Machine;
+
+    fn comparison_program() -> Vec<i64> {
+        vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8]
+    }
+
+    fn trace(tracer: Tracer<Vec<u8>>) -> Vec<u8> {
+        let mut tracer = tracer;
+        let mut machine = Machine::new(comparison_program());
+        machine.push_input(8);
+        machine.run_with(|step| tracer.record(step)).unwrap();
+        tracer.finish().unwrap()
+    }
+
+    #[test]
+    fn writes_json_lines() {
+        let output = String::from_utf8(trace(Tracer::new(Vec::new(), Format::JsonLines))).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0],
+            r#"{"step":0,"ip":0,"opcode":"in","instruction":"in 9","operands":[],"write":{"address":9,"old":-1,"new":8},"input":8,"output":null}"#
+        );
+        assert_eq!(
+            lines[1],
+            r#"{"step":1,"ip":2,"opcode":"eq","instruction":"eq 9, 10, 9","operands":[8,8],"write":{"address":9,"old":8,"new":1},"input":null,"output":null}"#
+        );
+        assert_eq!(
+            lines[2],
+            r#"{"step":2,"ip":6,"opcode":"out","instruction":"out 9","operands":[1],"write":null,"input":null,"output":1}"#
+        );
+    }
+
+    #[test]
+    fn filters_steps() {
+        let output = trace(Tracer::new(Vec::new(), Format::JsonLines).addresses(2..9).opcodes(vec![Opcode::Output, Opcode::Input]));
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.lines().count(), 1);
+        assert!(output.starts_with(r#"{"step":2,"ip":6,"#));
+    }
+
+    #[test]
+    fn round_trips_binary_traces() {
+        let binary = trace(Tracer::new(Vec::new(), Format::Binary));
+        let records = read_binary(&binary[..]).unwrap();
+
+        let mut expected = Vec::new();
+        let mut machine = Machine::new(comparison_program());
+        machine.push_input(8);
+        machine.run_with(|step| expected.push(Record::new(expected.len() as u64, step))).unwrap();
+
+        assert_eq!(records, expected);
+        assert_eq!(records[2].output, Some(1));
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(read_binary(&b"3,9,8"[..]).is_err());
+    }
+}
@@ -0,0 +1,91 @@
+// LEB128 style variable length integers, signed values are zigzag encoded first so small
+// negative numbers stay small
+use std::io::{self, Read, Write};
+
+pub fn write_u64<W: Write>(writer: &mut W, mut value: u64) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return writer.write_all(&[byte]);
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+pub fn write_i64<W: Write>(writer: &mut W, value: i64) -> io::Result<()> {
+    write_u64(writer, ((value << 1) ^ (value >> 63)) as u64)
+}
+
+// Returns None if the reader is already at its end
+pub fn read_u64<R: Read>(reader: &mut R) -> io::Result<Option<u64>> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let mut byte = [0u8];
+        if reader.read(&mut byte)? == 0 {
+            return if shift == 0 {
+                Ok(None)
+            } else {
+                Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated varint"))
+            };
+        }
+
+        if shift >= 64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "varint is too long"));
+        }
+
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+        shift += 7;
+    }
+}
+
+pub fn read_i64<R: Read>(reader: &mut R) -> io::Result<Option<i64>> {
+    Ok(read_u64(reader)?.map(|v| ((v >> 1) as i64) ^ -((v & 1) as i64)))
+}
+
+// For values that have to be there, like the fields after the start of a record
+pub fn expect_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    read_u64(reader)?.ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "unexpected end of data"))
+}
+
+pub fn expect_i64<R: Read>(reader: &mut R) -> io::Result<i64> {
+    read_i64(reader)?.ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "unexpected end of data"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_values() {
+        let values = [0, 1, -1, 63, -64, 64, 300, -300, i64::MAX, i64::MIN];
+        let mut buffer = Vec::new();
+        for v in &values {
+            write_i64(&mut buffer, *v).unwrap();
+        }
+
+        let mut reader = &buffer[..];
+        for v in &values {
+            assert_eq!(read_i64(&mut reader).unwrap(), Some(*v));
+        }
+        assert_eq!(read_i64(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn small_values_take_one_byte() {
+        let mut buffer = Vec::new();
+        write_i64(&mut buffer, -5).unwrap();
+        write_u64(&mut buffer, 127).unwrap();
+        assert_eq!(buffer, vec![9, 127]);
+    }
+
+    #[test]
+    fn rejects_truncated_values() {
+        let mut reader: &[u8] = &[0x80];
+        assert!(read_u64(&mut reader).is_err());
+    }
+}
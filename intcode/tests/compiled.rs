@@ -0,0 +1,63 @@
+// Programs compiled with `intcode compile`, run against the interpreter. To update one after
+// changing the compiler, run `intcode compile` on its PROGRAM again.
+use intcode::machine::Compiled;
+use intcode::{compile, Machine};
+
+#[path = "compiled/comparison.rs"]
+mod comparison;
+#[path = "compiled/countdown.rs"]
+mod countdown;
+#[path = "compiled/quine.rs"]
+mod quine;
+#[path = "compiled/self_modifying.rs"]
+mod self_modifying;
+
+fn assert_equivalent(program: &[i64], code: Compiled, inputs: &[i64]) {
+    let mut interpreted = Machine::new(program.to_vec());
+    interpreted.extend_inputs(inputs.iter().copied());
+    let mut compiled = interpreted.clone();
+
+    assert_eq!(compiled.run_compiled(code), interpreted.run());
+    assert_eq!(compiled.snapshot(), interpreted.snapshot());
+}
+
+#[test]
+fn generated_code_is_up_to_date() {
+    assert_eq!(compile::to_rust(comparison::PROGRAM), include_str!("compiled/comparison.rs"));
+    assert_eq!(compile::to_rust(countdown::PROGRAM), include_str!("compiled/countdown.rs"));
+    assert_eq!(compile::to_rust(quine::PROGRAM), include_str!("compiled/quine.rs"));
+    assert_eq!(compile::to_rust(self_modifying::PROGRAM), include_str!("compiled/self_modifying.rs"));
+}
+
+#[test]
+fn runs_like_the_interpreter() {
+    for input in 6..=10 {
+        assert_equivalent(comparison::PROGRAM, comparison::run, &[input]);
+    }
+    assert_equivalent(countdown::PROGRAM, countdown::run, &[1000]);
+    assert_equivalent(quine::PROGRAM, quine::run, &[]);
+    assert_equivalent(self_modifying::PROGRAM, self_modifying::run, &[]);
+}
+
+#[test]
+fn hands_over_when_waiting_for_input() {
+    let mut machine = Machine::new(comparison::PROGRAM.to_vec());
+    assert_eq!(machine.run_compiled(comparison::run), Ok(intcode::State::AwaitingInput));
+    machine.push_input(9);
+    assert_eq!(machine.run_compiled(comparison::run), Ok(intcode::State::Halted));
+    assert_eq!(machine.take_outputs(), vec![1001]);
+}
+
+#[test]
+fn interprets_changed_programs() {
+    // Make the countdown output the address of the counter instead of its value
+    let mut machine = Machine::new(countdown::PROGRAM.to_vec());
+    machine.write(9, 104);
+    let mut interpreted = machine.clone();
+    machine.push_input(3);
+    interpreted.push_input(3);
+
+    assert_eq!(machine.run_compiled(countdown::run), interpreted.run());
+    assert_eq!(machine.snapshot(), interpreted.snapshot());
+    assert_eq!(machine.take_outputs(), vec![12]);
+}
@@ -0,0 +1,142 @@
+// Generated from an Intcode program by `intcode compile`, changes will be overwritten
+#![allow(clippy::all, unused)]
+
+use intcode::machine::{Cpu, Error, Exit};
+
+pub const PROGRAM: &[i64] = &[3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0, 0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4, 20, 1105, 1, 46, 98, 99];
+
+// Cells holding compiled instructions
+const CODE: &[(usize, usize)] = &[(0, 19), (22, 45), (46, 47)];
+
+pub fn run(cpu: &mut Cpu, mut ip: usize) -> Result<Exit, Error> {
+    if !cpu.code_unchanged(PROGRAM, CODE) {
+        return Ok(Exit::Interpret { at: ip });
+    }
+
+    loop {
+        match ip {
+            0 => {
+                // 0: in 21
+                if cpu.inputs.is_empty() {
+                    return Ok(Exit::Interpret { at: 0 });
+                }
+                let t = cpu.address(0, 21)?;
+                let value = cpu.inputs.pop_front().unwrap();
+                cpu.store(t, value);
+                cpu.steps += 1;
+                // 2: eq 21, #8, 20
+                let a: i64 = cpu.load(2, 21)?;
+                let b: i64 = 8;
+                let t = cpu.address(2, 20)?;
+                cpu.store(t, (a == b) as i64);
+                cpu.steps += 1;
+                // 6: jt 20, #22
+                let a: i64 = cpu.load(6, 20)?;
+                let b: i64 = 22;
+                if a != 0 {
+                    ip = cpu.address(6, b)?;
+                    cpu.steps += 1;
+                    continue;
+                }
+                cpu.steps += 1;
+                ip = 9;
+            }
+            9 => {
+                // 9: lt #8, 21, 20
+                let a: i64 = 8;
+                let b: i64 = cpu.load(9, 21)?;
+                let t = cpu.address(9, 20)?;
+                cpu.store(t, (a < b) as i64);
+                cpu.steps += 1;
+                // 13: jf 20, #31
+                let a: i64 = cpu.load(13, 20)?;
+                let b: i64 = 31;
+                if a == 0 {
+                    ip = cpu.address(13, b)?;
+                    cpu.steps += 1;
+                    continue;
+                }
+                cpu.steps += 1;
+                ip = 16;
+            }
+            16 => {
+                // 16: jf #0, #36
+                let a: i64 = 0;
+                let b: i64 = 36;
+                if a == 0 {
+                    ip = cpu.address(16, b)?;
+                    cpu.steps += 1;
+                    continue;
+                }
+                cpu.steps += 1;
+                ip = 19;
+            }
+            22 => {
+                // 22: mul 21, #125, 20
+                let a: i64 = cpu.load(22, 21)?;
+                let b: i64 = 125;
+                let t = cpu.address(22, 20)?;
+                cpu.store(t, a.wrapping_mul(b));
+                cpu.steps += 1;
+                // 26: out 20
+                let a: i64 = cpu.load(26, 20)?;
+                cpu.outputs.push_back(a);
+                cpu.steps += 1;
+                // 28: jt #1, #46
+                let a: i64 = 1;
+                let b: i64 = 46;
+                if a != 0 {
+                    ip = cpu.address(28, b)?;
+                    cpu.steps += 1;
+                    continue;
+                }
+                cpu.steps += 1;
+                ip = 31;
+            }
+            31 => {
+                // 31: out #999
+                let a: i64 = 999;
+                cpu.outputs.push_back(a);
+                cpu.steps += 1;
+                // 33: jt #1, #46
+                let a: i64 = 1;
+                let b: i64 = 46;
+                if a != 0 {
+                    ip = cpu.address(33, b)?;
+                    cpu.steps += 1;
+                    continue;
+                }
+                cpu.steps += 1;
+                ip = 36;
+            }
+            36 => {
+                // 36: add #1000, #1, 20
+                let a: i64 = 1000;
+                let b: i64 = 1;
+                let t = cpu.address(36, 20)?;
+                cpu.store(t, a.wrapping_add(b));
+                cpu.steps += 1;
+                // 40: out 20
+                let a: i64 = cpu.load(40, 20)?;
+                cpu.outputs.push_back(a);
+                cpu.steps += 1;
+                // 42: jt #1, #46
+                let a: i64 = 1;
+                let b: i64 = 46;
+                if a != 0 {
+                    ip = cpu.address(42, b)?;
+                    cpu.steps += 1;
+                    continue;
+                }
+                cpu.steps += 1;
+                ip = 45;
+            }
+            46 => {
+                // 46: hlt
+                cpu.steps += 1;
+                return Ok(Exit::Halted { at: 46 });
+            }
+            _ => return Ok(Exit::Interpret { at: ip }),
+        }
+    }
+}
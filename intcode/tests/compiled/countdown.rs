@@ -0,0 +1,59 @@
+// Generated from an Intcode program by `intcode compile`, changes will be overwritten
+#![allow(clippy::all, unused)]
+
+use intcode::machine::{Cpu, Error, Exit};
+
+pub const PROGRAM: &[i64] = &[3, 12, 1001, 12, -1, 12, 1005, 12, 2, 4, 12, 99, 0];
+
+// Cells holding compiled instructions
+const CODE: &[(usize, usize)] = &[(0, 12)];
+
+pub fn run(cpu: &mut Cpu, mut ip: usize) -> Result<Exit, Error> {
+    if !cpu.code_unchanged(PROGRAM, CODE) {
+        return Ok(Exit::Interpret { at: ip });
+    }
+
+    loop {
+        match ip {
+            0 => {
+                // 0: in 12
+                if cpu.inputs.is_empty() {
+                    return Ok(Exit::Interpret { at: 0 });
+                }
+                let t = cpu.address(0, 12)?;
+                let value = cpu.inputs.pop_front().unwrap();
+                cpu.store(t, value);
+                cpu.steps += 1;
+                ip = 2;
+            }
+            2 => {
+                // 2: add 12, #-1, 12
+                let a: i64 = cpu.load(2, 12)?;
+                let b: i64 = -1;
+                let t = cpu.address(2, 12)?;
+                cpu.store(t, a.wrapping_add(b));
+                cpu.steps += 1;
+                // 6: jt 12, #2
+                let a: i64 = cpu.load(6, 12)?;
+                let b: i64 = 2;
+                if a != 0 {
+                    ip = cpu.address(6, b)?;
+                    cpu.steps += 1;
+                    continue;
+                }
+                cpu.steps += 1;
+                ip = 9;
+            }
+            9 => {
+                // 9: out 12
+                let a: i64 = cpu.load(9, 12)?;
+                cpu.outputs.push_back(a);
+                cpu.steps += 1;
+                // 11: hlt
+                cpu.steps += 1;
+                return Ok(Exit::Halted { at: 11 });
+            }
+            _ => return Ok(Exit::Interpret { at: ip }),
+        }
+    }
+}
@@ -0,0 +1,58 @@
+// Generated from an Intcode program by `intcode compile`, changes will be overwritten
+#![allow(clippy::all, unused)]
+
+use intcode::machine::{Cpu, Error, Exit};
+
+pub const PROGRAM: &[i64] = &[109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99];
+
+// Cells holding compiled instructions
+const CODE: &[(usize, usize)] = &[(0, 16)];
+
+pub fn run(cpu: &mut Cpu, mut ip: usize) -> Result<Exit, Error> {
+    if !cpu.code_unchanged(PROGRAM, CODE) {
+        return Ok(Exit::Interpret { at: ip });
+    }
+
+    loop {
+        match ip {
+            0 => {
+                // 0: arb #1
+                let a: i64 = 1;
+                cpu.relative_base = cpu.relative_base.wrapping_add(a);
+                cpu.steps += 1;
+                // 2: out @-1
+                let a: i64 = cpu.load(2, cpu.relative_base.wrapping_add(-1))?;
+                cpu.outputs.push_back(a);
+                cpu.steps += 1;
+                // 4: add 100, #1, 100
+                let a: i64 = cpu.load(4, 100)?;
+                let b: i64 = 1;
+                let t = cpu.address(4, 100)?;
+                cpu.store(t, a.wrapping_add(b));
+                cpu.steps += 1;
+                // 8: eq 100, #16, 101
+                let a: i64 = cpu.load(8, 100)?;
+                let b: i64 = 16;
+                let t = cpu.address(8, 101)?;
+                cpu.store(t, (a == b) as i64);
+                cpu.steps += 1;
+                // 12: jf 101, #0
+                let a: i64 = cpu.load(12, 101)?;
+                let b: i64 = 0;
+                if a == 0 {
+                    ip = cpu.address(12, b)?;
+                    cpu.steps += 1;
+                    continue;
+                }
+                cpu.steps += 1;
+                ip = 15;
+            }
+            15 => {
+                // 15: hlt
+                cpu.steps += 1;
+                return Ok(Exit::Halted { at: 15 });
+            }
+            _ => return Ok(Exit::Interpret { at: ip }),
+        }
+    }
+}
@@ -0,0 +1,39 @@
+// Generated from an Intcode program by `intcode compile`, changes will be overwritten
+#![allow(clippy::all, unused)]
+
+use intcode::machine::{Cpu, Error, Exit};
+
+pub const PROGRAM: &[i64] = &[1101, 1100, 1, 8, 1105, 1, 8, 99, 1102, 3, 4, 0, 99];
+
+// Cells holding compiled instructions
+const CODE: &[(usize, usize)] = &[(0, 7), (8, 13)];
+
+pub fn run(cpu: &mut Cpu, mut ip: usize) -> Result<Exit, Error> {
+    if !cpu.code_unchanged(PROGRAM, CODE) {
+        return Ok(Exit::Interpret { at: ip });
+    }
+
+    loop {
+        match ip {
+            0 => {
+                // 0: add #1100, #1, 8
+                let a: i64 = 1100;
+                let b: i64 = 1;
+                let t = cpu.address(0, 8)?;
+                cpu.store(t, a.wrapping_add(b));
+                cpu.steps += 1;
+                return Ok(Exit::Interpret { at: 4 });
+            }
+            8 => {
+                // 8: mul #3, #4, 0
+                let a: i64 = 3;
+                let b: i64 = 4;
+                let t = cpu.address(8, 0)?;
+                cpu.store(t, a.wrapping_mul(b));
+                cpu.steps += 1;
+                return Ok(Exit::Interpret { at: 12 });
+            }
+            _ => return Ok(Exit::Interpret { at: ip }),
+        }
+    }
+}